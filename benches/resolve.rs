@@ -0,0 +1,85 @@
+// Throughput of route resolution with many workers sharing one router, run with `cargo bench`
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    thread,
+    time::Instant,
+};
+
+use core::server::{
+    auth::{AuthBuilder, AuthManager, Authentication},
+    request::ServerRequest,
+    response::{IntoResponse, ServerResponse},
+    router::router::{PathArguments, Router, RouterBuilder},
+    ServerResult,
+};
+use http::{Request, StatusCode};
+
+const REQUESTS_PER_WORKER: usize = 200_000;
+const WORKERS: [usize; 5] = [1, 2, 4, 8, 16];
+
+fn ok(_: ServerRequest, _: PathArguments) -> ServerResult<ServerResponse> {
+    Ok(ServerResponse::create(StatusCode::OK, ""))
+}
+
+fn router() -> Router {
+    let mut builder = RouterBuilder::new();
+    for area in ["kitchen", "living-room", "bedroom", "garage", "garden"] {
+        builder
+            .get(format!("/api/{area}/lights"), ok)
+            .get(format!("/api/{area}/sensors/[id:u32]"), ok)
+            .post(format!("/api/{area}/sensors/[id:u32]"), ok)
+            .get(format!("/api/{area}/sensors/[name]/history"), ok);
+    }
+    builder
+        .get("/devices/[id]", ok)
+        .get("/devices/[id]/edit", ok)
+        .get("/files/[...path]", ok);
+    builder.build().expect("Invalid benchmark routes")
+}
+
+fn requests() -> Vec<ServerRequest> {
+    [
+        "/api/kitchen/lights",
+        "/api/garage/sensors/42",
+        "/api/garden/sensors/rain/history",
+        "/devices/7/edit",
+        "/files/photos/2024/summer.jpg",
+    ]
+    .iter()
+    .map(|path| {
+        let request = Request::get(format!("{path}?username=user&password=pass"))
+            .body(None)
+            .unwrap();
+        ServerRequest::new(request)
+    })
+    .collect()
+}
+
+fn run(router: &Router, auth: &AuthManager, requests: &[ServerRequest], workers: usize) -> f64 {
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                for request in requests.iter().cycle().take(REQUESTS_PER_WORKER) {
+                    router.resolve(request.clone(), auth).unwrap();
+                }
+            });
+        }
+    });
+    (workers * REQUESTS_PER_WORKER) as f64 / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let router = router();
+    let auth = AuthBuilder::new()
+        .allow_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        .allow_user(Authentication::new("user", "pass"))
+        .build();
+    let requests = requests();
+
+    println!("workers  requests/s");
+    for workers in WORKERS {
+        let throughput = run(&router, &auth, &requests, workers);
+        println!("{:>7}  {:>10.0}", workers, throughput);
+    }
+}
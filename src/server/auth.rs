@@ -1,6 +1,8 @@
-use std::{collections::HashSet, net::IpAddr};
+use std::{collections::HashSet, fmt::Display, net::IpAddr};
 
-use super::{request::ServerRequest, ServerResult};
+use http::StatusCode;
+
+use super::{request::ServerRequest, ServerError, ServerResult};
 
 #[allow(dead_code)]
 #[derive(Clone, Eq, Hash, PartialEq)]
@@ -24,6 +26,36 @@ impl Authentication {
     }
 }
 
+// Authentication required by a route, groups can open their routes to everyone or restrict them
+// to some of the allowed users
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AuthPolicy {
+    Public,
+    #[default]
+    Authenticated,
+    Users(HashSet<String>),
+}
+
+impl AuthPolicy {
+    pub fn users(usernames: &[&str]) -> Self {
+        AuthPolicy::Users(usernames.iter().map(|name| name.to_string()).collect())
+    }
+}
+
+impl Display for AuthPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthPolicy::Public => write!(f, "public"),
+            AuthPolicy::Authenticated => write!(f, "authenticated"),
+            AuthPolicy::Users(users) => {
+                let mut users = users.iter().map(String::as_str).collect::<Vec<_>>();
+                users.sort();
+                write!(f, "users({})", users.join(", "))
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct AuthManager {
     allowed_addresses: HashSet<IpAddr>,
@@ -38,6 +70,24 @@ impl AuthManager {
     pub fn authenticate(&self, user: &Authentication) -> bool {
         self.allowed_users.contains(user)
     }
+
+    // Checks the credentials of a request against the policy of the route it resolved to
+    pub fn check(&self, request: &ServerRequest, policy: &AuthPolicy) -> ServerResult<()> {
+        let users = match policy {
+            AuthPolicy::Public => return Ok(()),
+            AuthPolicy::Authenticated => None,
+            AuthPolicy::Users(users) => Some(users),
+        };
+        let unauthorized = || ServerError::new(StatusCode::UNAUTHORIZED, "Authentication failed");
+        let auth = Authentication::from_request(request).map_err(|_| unauthorized())?;
+        if !self.authenticate(&auth) {
+            return Err(unauthorized());
+        }
+        if users.is_some_and(|users| !users.contains(&auth.username)) {
+            return Err(ServerError::new(StatusCode::FORBIDDEN, "Forbidden"));
+        }
+        Ok(())
+    }
 }
 
 pub struct AuthBuilder {
@@ -0,0 +1,355 @@
+use std::{fs::File, io, pin::Pin};
+
+use async_compression::{
+    futures::bufread::{BrotliEncoder, GzipEncoder, ZstdEncoder},
+    Level,
+};
+use futures_util::{
+    io::{AsyncRead, AsyncReadExt},
+    stream, TryStreamExt,
+};
+use http::{
+    header::{ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_TYPE, ETAG, VARY},
+    HeaderValue, StatusCode,
+};
+
+use super::{
+    request::RequestHead,
+    response::{BodyStream, FileBody, ResponseBody, ServerResponse},
+};
+
+// Size of the chunks handed to the connection while compressing
+const CHUNK_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    // Extension of the precompressed sibling of a static file
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zst",
+            Encoding::Gzip => "gz",
+        }
+    }
+}
+
+// Encodings are listed in order of preference, content types are matched by prefix
+#[derive(Debug, Clone)]
+pub struct Compression {
+    pub enabled: bool,
+    pub min_size: u64,
+    pub encodings: Vec<Encoding>,
+    pub content_types: Vec<&'static str>,
+    pub precompressed: bool,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_size: 1024,
+            encodings: vec![Encoding::Brotli, Encoding::Zstd, Encoding::Gzip],
+            content_types: vec![
+                "text/",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "application/manifest+json",
+                "application/wasm",
+                "image/svg+xml",
+            ],
+            precompressed: true,
+        }
+    }
+}
+
+impl Compression {
+    fn compressible(&self, response: &ServerResponse) -> bool {
+        let content_type = match response.headers().get(CONTENT_TYPE) {
+            Some(value) => value.to_str().unwrap_or_default(),
+            None => return false,
+        };
+        self.content_types
+            .iter()
+            .any(|prefix| content_type.starts_with(prefix))
+    }
+
+    // Encodings accepted by the client ordered by their q value, ties are broken by our preference
+    fn negotiate(&self, accept_encoding: &str) -> Vec<Encoding> {
+        let mut wildcard = 0.0;
+        let mut weights = vec![];
+        for item in accept_encoding.split(',') {
+            let mut params = item.split(';');
+            let name = params.next().unwrap_or_default().trim().to_lowercase();
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            match name.as_str() {
+                "*" => wildcard = q,
+                "x-gzip" => weights.push(("gzip".to_string(), q)),
+                _ => weights.push((name, q)),
+            }
+        }
+        let mut accepted = self
+            .encodings
+            .iter()
+            .filter_map(|encoding| {
+                let q = weights
+                    .iter()
+                    .find(|(name, _)| name == encoding.name())
+                    .map_or(wildcard, |(_, q)| *q);
+                (q > 0.0).then_some((*encoding, q))
+            })
+            .collect::<Vec<_>>();
+        accepted.sort_by(|a, b| b.1.total_cmp(&a.1));
+        accepted.into_iter().map(|(encoding, _)| encoding).collect()
+    }
+}
+
+// Responses of compressible types depend on Accept-Encoding whatever their status. The header is
+// set before a response is turned into a 304 or a 206, so those vary like the full response
+pub fn vary(config: &Compression, mut response: ServerResponse) -> ServerResponse {
+    let varies = response
+        .headers()
+        .get_all(VARY)
+        .iter()
+        .any(|value| value.as_bytes().eq_ignore_ascii_case(b"Accept-Encoding"));
+    if config.enabled
+        && !varies
+        && !response.headers().contains_key(CONTENT_ENCODING)
+        && config.compressible(&response)
+    {
+        response
+            .headers_mut()
+            .append(VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+    response
+}
+
+// Compresses complete 200 responses of compressible types, static files are swapped for their
+// precompressed siblings (style.css.br, style.css.gz...) when those exist
+pub fn apply(
+    config: &Compression,
+    request: &RequestHead,
+    response: ServerResponse,
+) -> ServerResponse {
+    let mut response = vary(config, response);
+    if !config.enabled
+        || response.status() != StatusCode::OK
+        || response.headers().contains_key(CONTENT_ENCODING)
+        || response.body().is_none()
+        || !config.compressible(&response)
+    {
+        return response;
+    }
+
+    let accepted = match request.header("Accept-Encoding") {
+        Some(header) => config.negotiate(header),
+        None => return response,
+    };
+    if config.precompressed {
+        if let Some(ResponseBody::File(file)) = response.body_mut() {
+            if let Some(encoding) = swap_precompressed(file, &accepted) {
+                set_encoding(&mut response, encoding);
+                return response;
+            }
+        }
+    }
+
+    let encoding = match accepted.first() {
+        Some(encoding) => *encoding,
+        None => return response,
+    };
+    let length = response.body().as_ref().and_then(|b| b.content_length());
+    if length.is_some_and(|length| length < config.min_size) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    // Ranges refer to the uncompressed bytes, they can't be served from an on the fly encoding
+    parts.headers.remove(ACCEPT_RANGES);
+    let body = body.map(|body| ResponseBody::Stream(compress(body, encoding)));
+    let mut response = ServerResponse::from_parts(parts, body);
+    set_encoding(&mut response, encoding);
+    response
+}
+
+fn swap_precompressed(file: &mut FileBody, accepted: &[Encoding]) -> Option<Encoding> {
+    if file.start != 0 {
+        return None;
+    }
+    for encoding in accepted {
+        let mut path = file.path.clone().into_os_string();
+        path.push(".");
+        path.push(encoding.extension());
+        let sibling = match File::open(&path) {
+            Ok(sibling) => sibling,
+            Err(_) => continue,
+        };
+        match sibling.metadata() {
+            Ok(metadata) if metadata.is_file() => {
+                file.len = metadata.len();
+            }
+            _ => continue,
+        }
+        file.file = sibling;
+        file.path = path.into();
+        return Some(*encoding);
+    }
+    None
+}
+
+// The encoded body is a different sequence of bytes, so its entity tag can only be weak
+fn set_encoding(response: &mut ServerResponse, encoding: Encoding) {
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+    if let Some(etag) = headers.get(ETAG).and_then(|v| v.to_str().ok()) {
+        if !etag.starts_with("W/") {
+            let weak = HeaderValue::from_str(&format!("W/{}", etag)).unwrap();
+            headers.insert(ETAG, weak);
+        }
+    }
+}
+
+fn compress(body: ResponseBody, encoding: Encoding) -> BodyStream {
+    let reader = body.into_stream().into_async_read();
+    let encoder: Pin<Box<dyn AsyncRead + Send>> = match encoding {
+        Encoding::Brotli => Box::pin(BrotliEncoder::with_quality(reader, Level::Precise(4))),
+        Encoding::Zstd => Box::pin(ZstdEncoder::new(reader)),
+        Encoding::Gzip => Box::pin(GzipEncoder::new(reader)),
+    };
+    Box::pin(stream::try_unfold(encoder, |mut encoder| async move {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let read = encoder.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok::<_, io::Error>(None);
+        }
+        chunk.truncate(read);
+        Ok(Some((chunk, encoder)))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Request;
+
+    use super::*;
+    use crate::server::{request::ServerRequest, response::IntoResponse};
+
+    fn negotiate(accept_encoding: &str) -> Vec<Encoding> {
+        Compression::default().negotiate(accept_encoding)
+    }
+
+    fn get(accept_encoding: &str) -> RequestHead {
+        let request = Request::get("/app.css").header("Accept-Encoding", accept_encoding);
+        ServerRequest::new(request.body(None).unwrap()).head()
+    }
+
+    #[test]
+    fn negotiates_by_q_value_then_preference() {
+        use Encoding::*;
+        assert_eq!(negotiate("gzip, br"), [Brotli, Gzip]);
+        assert_eq!(negotiate("gzip;q=1.0, br;q=0.5"), [Gzip, Brotli]);
+        assert_eq!(negotiate("GZIP ; q=0.8, zstd;q=0.9"), [Zstd, Gzip]);
+        assert_eq!(negotiate("x-gzip"), [Gzip]);
+        assert_eq!(negotiate("identity"), []);
+        assert_eq!(negotiate(""), []);
+    }
+
+    #[test]
+    fn wildcard_covers_unlisted_encodings() {
+        use Encoding::*;
+        assert_eq!(negotiate("*"), [Brotli, Zstd, Gzip]);
+        assert_eq!(negotiate("gzip;q=0.5, *;q=0.1"), [Gzip, Brotli, Zstd]);
+        assert_eq!(negotiate("gzip, *;q=0"), [Gzip]);
+        assert_eq!(negotiate("br;q=0, *"), [Zstd, Gzip]);
+    }
+
+    #[test]
+    fn refused_encodings_are_never_picked() {
+        assert_eq!(negotiate("gzip;q=0"), []);
+        assert_eq!(negotiate("br;q=0, gzip;q=0.000"), []);
+    }
+
+    // A stylesheet with a brotli sibling but no gzip one
+    fn precompressed(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("compression-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("app.css"), "body { color: red }".repeat(100)).unwrap();
+        std::fs::write(dir.join("app.css.br"), "brotli").unwrap();
+        dir
+    }
+
+    fn served(response: &ServerResponse) -> &FileBody {
+        match response.body() {
+            Some(ResponseBody::File(file)) => file,
+            _ => panic!("Expected a file body"),
+        }
+    }
+
+    #[test]
+    fn serves_precompressed_siblings() {
+        let dir = precompressed("sibling");
+        let path = dir.join("app.css");
+        let response = ServerResponse::file(path.to_str().unwrap()).unwrap();
+        let etag = response.headers()[ETAG].to_str().unwrap().to_string();
+        let response = apply(&Compression::default(), &get("gzip, br"), response);
+        assert_eq!(response.headers()[CONTENT_ENCODING], "br");
+        assert_eq!(response.headers()[ETAG], format!("W/{}", etag).as_str());
+        assert_eq!(response.headers()[VARY], "Accept-Encoding");
+        assert_eq!(served(&response).path, dir.join("app.css.br"));
+        assert_eq!(served(&response).len, 6);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn compresses_without_a_sibling() {
+        let dir = precompressed("fallback");
+        let path = dir.join("app.css");
+        let config = Compression::default();
+
+        // No gzip sibling, the file is compressed while it is sent
+        let response = ServerResponse::file(path.to_str().unwrap()).unwrap();
+        let response = apply(&config, &get("gzip"), response);
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        assert!(!response.headers().contains_key(ACCEPT_RANGES));
+        assert!(matches!(response.body(), Some(ResponseBody::Stream(_))));
+
+        // Siblings are ignored when disabled
+        let config = Compression {
+            precompressed: false,
+            ..config
+        };
+        let response = ServerResponse::file(path.to_str().unwrap()).unwrap();
+        let response = apply(&config, &get("br"), response);
+        assert!(matches!(response.body(), Some(ResponseBody::Stream(_))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn leaves_other_responses_alone() {
+        let config = Compression::default();
+        let small = ServerResponse::create(StatusCode::OK, "tiny");
+        let response = apply(&config, &get("gzip"), small);
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(response.headers()[VARY], "Accept-Encoding");
+
+        let refused = ServerResponse::create(StatusCode::OK, "x".repeat(4096));
+        let response = apply(&config, &get("gzip;q=0"), refused);
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    hash::{DefaultHasher, Hasher},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use http::{
+    header::{CACHE_CONTROL, ETAG, EXPIRES, LAST_MODIFIED, VARY},
+    HeaderValue, Method, StatusCode,
+};
+
+use super::{
+    request::RequestHead,
+    response::{ResponseBody, ServerResponse},
+};
+
+// Headers a 304 has to repeat from the response it replaces
+const KEPT_HEADERS: [http::HeaderName; 5] = [CACHE_CONTROL, ETAG, EXPIRES, LAST_MODIFIED, VARY];
+
+// Entity tag of a file, changes whenever the file is modified or resized
+pub fn file_etag(modified: SystemTime, len: u64) -> String {
+    let modified = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", modified, len)
+}
+
+// Entity tag computed from the content itself, for responses that aren't backed by a file
+pub fn content_etag(content: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(content);
+    format!("\"{:x}-{:x}\"", hasher.finish(), content.len())
+}
+
+// Sets an ETag from the body of a successful in memory response, unless the handler already set
+// one
+pub fn with_etag(mut response: ServerResponse) -> ServerResponse {
+    if response.status() != StatusCode::OK || response.headers().contains_key(ETAG) {
+        return response;
+    }
+    if let Some(ResponseBody::Bytes(bytes)) = response.body() {
+        let etag = HeaderValue::from_str(&content_etag(bytes)).unwrap();
+        response.headers_mut().insert(ETAG, etag);
+    }
+    response
+}
+
+// Replaces successful GET responses with 304 Not Modified when the client already holds the
+// current representation, based on the ETag and Last-Modified headers of the response
+pub fn apply(request: &RequestHead, response: ServerResponse) -> ServerResponse {
+    if request.method != Method::GET || response.status() != StatusCode::OK {
+        return response;
+    }
+    let fresh = match request.header("If-None-Match") {
+        Some(tags) => match header_str(&response, ETAG) {
+            Some(etag) => matches_etag(tags, etag),
+            None => false,
+        },
+        // If-Modified-Since is ignored when If-None-Match is present
+        None => match (
+            request.header("If-Modified-Since"),
+            header_str(&response, LAST_MODIFIED),
+        ) {
+            (Some(since), Some(modified)) => not_modified_since(since, modified),
+            _ => false,
+        },
+    };
+    if !fresh {
+        return response;
+    }
+
+    let mut not_modified = ServerResponse::new(None);
+    *not_modified.status_mut() = StatusCode::NOT_MODIFIED;
+    for name in KEPT_HEADERS {
+        if let Some(value) = response.headers().get(&name) {
+            not_modified.headers_mut().insert(name, value.clone());
+        }
+    }
+    not_modified
+}
+
+fn header_str(response: &ServerResponse, name: http::HeaderName) -> Option<&str> {
+    response.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+// If-None-Match uses the weak comparison, W/"a" matches "a"
+fn matches_etag(tags: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    tags.split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+fn not_modified_since(since: &str, modified: &str) -> bool {
+    match (
+        httpdate::parse_http_date(since),
+        httpdate::parse_http_date(modified),
+    ) {
+        (Ok(since), Ok(modified)) => modified <= since,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Request;
+
+    use super::*;
+    use crate::server::{request::ServerRequest, response::IntoResponse};
+
+    fn get(if_none_match: Option<&str>) -> RequestHead {
+        let mut request = Request::get("/status");
+        if let Some(tags) = if_none_match {
+            request = request.header("If-None-Match", tags);
+        }
+        ServerRequest::new(request.body(None).unwrap()).head()
+    }
+
+    #[test]
+    fn handler_responses_get_an_etag() {
+        let response = with_etag(ServerResponse::create(StatusCode::OK, "ok"));
+        let etag = response.headers().get(ETAG).unwrap().to_str().unwrap();
+        assert_eq!(etag, content_etag(b"ok"));
+
+        let response = with_etag(ServerResponse::create(StatusCode::NOT_FOUND, "missing"));
+        assert!(!response.headers().contains_key(ETAG));
+    }
+
+    #[test]
+    fn matching_etag_is_not_modified() {
+        let etag = content_etag(b"ok");
+        let response = with_etag(ServerResponse::create(StatusCode::OK, "ok"));
+        let response = apply(&get(Some(&etag)), response);
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(ETAG).unwrap(), etag.as_str());
+
+        let response = with_etag(ServerResponse::create(StatusCode::OK, "ok"));
+        let response = apply(&get(Some("\"other\"")), response);
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
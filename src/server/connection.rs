@@ -1,8 +1,12 @@
 use super::ServerError;
 use super::{
-    response::{IntoResponse, ServerResponse},
+    error_page::ErrorRenderer,
+    response::{IntoResponse, ResponseBody, ServerResponse},
     ServerResult,
 };
+use futures_util::StreamExt;
+use http::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
+use http::{HeaderValue, StatusCode};
 use std::net::SocketAddr;
 use tokio::{io::AsyncWriteExt, net::TcpStream};
 use tokio_rustls::server::TlsStream;
@@ -13,21 +17,66 @@ pub struct Connection {
 }
 
 impl Connection {
-    pub async fn reply(&mut self, response: ServerResponse) -> ServerResult<()> {
-        match self.stream.write_all(&response.into_bytes()).await {
+    // Bodies with an unknown length are sent chunked, every chunk is written before the next one
+    // is pulled so slow clients hold back the producer instead of filling memory
+    pub async fn reply(&mut self, mut response: ServerResponse) -> ServerResult<()> {
+        let length = response
+            .body()
+            .as_ref()
+            .map_or(Some(0), ResponseBody::content_length);
+        let status = response.status();
+        let headers = response.headers_mut();
+        // 304 and 204 never carry a body, so they don't describe one either
+        if status != StatusCode::NOT_MODIFIED && status != StatusCode::NO_CONTENT {
+            match length {
+                Some(length) => headers.insert(CONTENT_LENGTH, HeaderValue::from(length)),
+                None => headers.insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked")),
+            };
+        }
+        self.write(&response.head_bytes()).await?;
+
+        if let Some(body) = response.into_body() {
+            let mut body = body.into_stream();
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.map_err(|e| ServerError::err(&format!("{}", e)))?;
+                if length.is_some() {
+                    self.write(&chunk).await?;
+                } else if !chunk.is_empty() {
+                    self.write(format!("{:x}\r\n", chunk.len()).as_bytes())
+                        .await?;
+                    self.write(&chunk).await?;
+                    self.write(b"\r\n").await?;
+                }
+            }
+            if length.is_none() {
+                self.write(b"0\r\n\r\n").await?;
+            }
+        }
+        self.stream
+            .flush()
+            .await
+            .map_err(|e| ServerError::err(&format!("{}", e)))
+    }
+
+    pub async fn reply_error(
+        &mut self,
+        error: ServerError,
+        render: ErrorRenderer,
+    ) -> ServerResult<()> {
+        let mut response = render(&error);
+        for (name, value) in error.headers {
+            response.headers_mut().append(name, value);
+        }
+        self.reply(response).await
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> ServerResult<()> {
+        match self.stream.write_all(bytes).await {
             Ok(_) => Ok(()),
             Err(e) => Err(ServerError::new(
-                http::StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::INTERNAL_SERVER_ERROR,
                 &format!("{}", e),
             )),
         }
     }
-
-    pub async fn reply_error(&mut self, error: ServerError) -> ServerResult<()> {
-        self.reply(ServerResponse::create(
-            error.code,
-            error.error.as_bytes().to_vec(),
-        ))
-        .await
-    }
 }
@@ -0,0 +1,304 @@
+use std::time::Duration;
+
+use http::{
+    header::{
+        ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+        ACCESS_CONTROL_MAX_AGE, VARY,
+    },
+    HeaderName, HeaderValue, Method, StatusCode,
+};
+
+use super::{
+    request::ServerRequest,
+    response::{IntoResponse, ServerResponse},
+    ServerError, ServerResult,
+};
+
+// Cross-origin access to the routes. Origins are compared with the Origin header as sent by the
+// browser (ex: https://dashboard.local:3000), "*" allows any of them but can't be combined with
+// credentials. Without methods every method of the route is allowed, header names are compared
+// regardless of case
+#[derive(Debug, Clone, Default)]
+pub struct Cors {
+    pub origins: Vec<String>,
+    pub methods: Vec<Method>,
+    pub headers: Vec<String>,
+    pub any_header: bool,
+    pub expose_headers: Vec<String>,
+    pub credentials: bool,
+    pub max_age: Option<Duration>,
+}
+
+impl Cors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn origin(mut self, origin: &str) -> Self {
+        self.origins.push(origin.trim_end_matches('/').to_string());
+        self
+    }
+
+    pub fn any_origin(self) -> Self {
+        self.origin("*")
+    }
+
+    pub fn method(mut self, method: Method) -> Self {
+        self.methods.push(method);
+        self
+    }
+
+    pub fn header(mut self, name: &str) -> Self {
+        self.headers.push(name.to_lowercase());
+        self
+    }
+
+    pub fn any_header(mut self) -> Self {
+        self.any_header = true;
+        self
+    }
+
+    // Response headers the browser lets scripts read, besides the safelisted ones
+    pub fn expose(mut self, name: &str) -> Self {
+        self.expose_headers.push(name.to_string());
+        self
+    }
+
+    // Lets the browser send cookies and credentials, only to the origins listed explicitly
+    pub fn credentials(mut self, credentials: bool) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    // How long the browser can cache a preflight
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|o| o == "*" || o == origin)
+    }
+
+    // Any website could make credentialed requests if every origin was allowed with credentials
+    pub fn validate(&self) -> ServerResult<()> {
+        if self.credentials && self.any_origin_allowed() {
+            return Err(ServerError::err(
+                "CORS credentials need an explicit list of origins",
+            ));
+        }
+        Ok(())
+    }
+
+    // Headers added to the response of a request. Only an allowed origin gets the CORS headers,
+    // but the response varies by origin whether the request has one or not
+    pub fn response_headers(&self, request: &ServerRequest) -> Vec<(HeaderName, HeaderValue)> {
+        let origin = match request.header("Origin") {
+            Some(origin) if self.allows_origin(origin) => origin,
+            _ if self.any_origin_allowed() => return vec![],
+            _ => return vec![(VARY, HeaderValue::from_static("Origin"))],
+        };
+        let mut headers = self.origin_headers(origin);
+        if !self.expose_headers.is_empty() {
+            headers.push((
+                ACCESS_CONTROL_EXPOSE_HEADERS,
+                header_value(&self.expose_headers.join(", ")),
+            ));
+        }
+        headers
+    }
+
+    // Answers a preflight for a route that handles the given methods. The browser blocks the
+    // request itself when its origin, method or headers aren't allowed
+    pub fn preflight(
+        &self,
+        request: &ServerRequest,
+        methods: &[&str],
+    ) -> ServerResult<ServerResponse> {
+        let forbidden = |message: &str| ServerError::new(StatusCode::FORBIDDEN, message);
+        let origin = match request.header("Origin") {
+            Some(origin) if self.allows_origin(origin) => origin,
+            _ => return Err(forbidden("Origin not allowed")),
+        };
+        let methods = methods
+            .iter()
+            .filter(|m| self.methods.is_empty() || self.methods.iter().any(|a| a == **m))
+            .copied()
+            .collect::<Vec<_>>();
+        match request.header("Access-Control-Request-Method") {
+            Some(method) if methods.contains(&method) => {}
+            _ => return Err(forbidden("Method not allowed")),
+        }
+        let requested = request
+            .header("Access-Control-Request-Headers")
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+        if !self.any_header && !requested.iter().all(|name| self.headers.contains(name)) {
+            return Err(forbidden("Header not allowed"));
+        }
+
+        let mut response = ServerResponse::create(StatusCode::NO_CONTENT, "");
+        let headers = response.headers_mut();
+        for (name, value) in self.origin_headers(origin) {
+            headers.append(name, value);
+        }
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_METHODS,
+            header_value(&methods.join(", ")),
+        );
+        if !requested.is_empty() {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_HEADERS,
+                header_value(&requested.join(", ")),
+            );
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.as_secs().into());
+        }
+        Ok(response)
+    }
+
+    fn any_origin_allowed(&self) -> bool {
+        self.origins.iter().any(|o| o == "*")
+    }
+
+    // The response depends on the origin unless any origin gets the wildcard
+    fn origin_headers(&self, origin: &str) -> Vec<(HeaderName, HeaderValue)> {
+        let mut headers = match self.any_origin_allowed() {
+            true => vec![(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"))],
+            false => vec![
+                (ACCESS_CONTROL_ALLOW_ORIGIN, header_value(origin)),
+                (VARY, HeaderValue::from_static("Origin")),
+            ],
+        };
+        if self.credentials {
+            headers.push((
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            ));
+        }
+        headers
+    }
+}
+
+// Requests a browser sends before a cross-origin request it can't send directly
+pub fn is_preflight(request: &ServerRequest) -> bool {
+    request.method() == Method::OPTIONS
+        && request.header("Origin").is_some()
+        && request.header("Access-Control-Request-Method").is_some()
+}
+
+// Values come from request headers or the configuration, which are valid header text
+fn header_value(value: &str) -> HeaderValue {
+    HeaderValue::from_str(value).unwrap_or_else(|_| HeaderValue::from_static(""))
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Request;
+
+    use super::*;
+
+    const DASHBOARD: &str = "https://dashboard.local:3000";
+
+    fn cors() -> Cors {
+        Cors::new()
+            .origin(DASHBOARD)
+            .header("Content-Type")
+            .credentials(true)
+            .max_age(Duration::from_secs(600))
+    }
+
+    fn preflight(origin: &str, method: &str, headers: Option<&str>) -> ServerRequest {
+        let mut request = Request::options("/api/lights")
+            .header("Origin", origin)
+            .header("Access-Control-Request-Method", method);
+        if let Some(headers) = headers {
+            request = request.header("Access-Control-Request-Headers", headers);
+        }
+        ServerRequest::new(request.body(None).unwrap())
+    }
+
+    fn header<'a>(response: &'a ServerResponse, name: &str) -> Option<&'a str> {
+        response.headers().get(name).map(|v| v.to_str().unwrap())
+    }
+
+    #[test]
+    fn answers_allowed_preflights() {
+        let request = preflight(DASHBOARD, "PUT", Some("content-type"));
+        let response = cors().preflight(&request, &["GET", "PUT"]).unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Origin"),
+            Some(DASHBOARD)
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Methods"),
+            Some("GET, PUT")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Headers"),
+            Some("content-type")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(header(&response, "Access-Control-Max-Age"), Some("600"));
+        assert_eq!(header(&response, "Vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn rejects_other_preflights() {
+        let forbidden = |request: ServerRequest| {
+            let error = cors().preflight(&request, &["GET"]).unwrap_err();
+            assert_eq!(error.code(), StatusCode::FORBIDDEN);
+        };
+        forbidden(preflight("https://evil.example", "GET", None));
+        forbidden(preflight(DASHBOARD, "DELETE", None));
+        forbidden(preflight(DASHBOARD, "GET", Some("x-secret")));
+    }
+
+    #[test]
+    fn responses_vary_by_origin() {
+        let request = |origin: Option<&str>| {
+            let mut request = Request::get("/api/lights");
+            if let Some(origin) = origin {
+                request = request.header("Origin", origin);
+            }
+            ServerRequest::new(request.body(None).unwrap())
+        };
+        let vary = (VARY, HeaderValue::from_static("Origin"));
+        let vary_only = vec![vary.clone()];
+        assert_eq!(cors().response_headers(&request(None)), vary_only);
+        assert_eq!(
+            cors().response_headers(&request(Some("https://evil.example"))),
+            vary_only
+        );
+        assert!(cors()
+            .response_headers(&request(Some(DASHBOARD)))
+            .contains(&vary));
+
+        let any = Cors::new().any_origin();
+        assert_eq!(any.response_headers(&request(None)), []);
+        assert_eq!(
+            any.response_headers(&request(Some(DASHBOARD))),
+            [(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"))]
+        );
+    }
+
+    #[test]
+    fn credentials_need_explicit_origins() {
+        assert!(cors().validate().is_ok());
+        assert!(Cors::new().any_origin().validate().is_ok());
+        assert!(Cors::new()
+            .any_origin()
+            .credentials(true)
+            .validate()
+            .is_err());
+    }
+}
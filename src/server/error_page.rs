@@ -0,0 +1,48 @@
+use super::{
+    response::{IntoResponse, ServerResponse},
+    router::static_dir::escape_html,
+    ServerError,
+};
+
+// Turns an error into the response sent to the client, renderers should only show the public
+// message of the error
+pub type ErrorRenderer = fn(&ServerError) -> ServerResponse;
+
+pub fn text(error: &ServerError) -> ServerResponse {
+    ServerResponse::create(error.code(), error.public_message())
+}
+
+pub fn html(error: &ServerError) -> ServerResponse {
+    let code = error.code();
+    let title = format!(
+        "{} {}",
+        code.as_u16(),
+        code.canonical_reason().unwrap_or_default()
+    );
+    let mut response = ServerResponse::html(format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>{title}</title></head>\n<body>\n<h1>{title}</h1>\n<p>{}</p>\n</body>\n</html>\n",
+        escape_html(error.public_message())
+    ));
+    *response.status_mut() = code;
+    response
+}
+
+// RFC 9457 problem document
+#[cfg(feature = "json")]
+pub fn problem_json(error: &ServerError) -> ServerResponse {
+    use http::{header::CONTENT_TYPE, HeaderValue};
+
+    let code = error.code();
+    let problem = serde_json::json!({
+        "type": "about:blank",
+        "title": code.canonical_reason().unwrap_or_default(),
+        "status": code.as_u16(),
+        "detail": error.public_message(),
+    });
+    let mut response = ServerResponse::create(code, problem.to_string());
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/problem+json"),
+    );
+    response
+}
@@ -0,0 +1,385 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs,
+    hash::{BuildHasher, Hasher},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use futures_util::Stream;
+use http::StatusCode;
+use multer::{Constraints, Multipart, SizeLimit};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use super::{query::QueryMap, ServerError, ServerResult};
+
+// Limits applied to multipart bodies, file parts are written to the upload directory as they
+// arrive so only text fields are kept in memory
+#[derive(Debug, Clone)]
+pub struct FormLimits {
+    pub upload_dir: PathBuf,
+    pub max_size: u64,
+    pub max_file_size: u64,
+    pub max_files: usize,
+    pub max_fields: usize,
+    pub max_field_size: usize,
+}
+
+impl Default for FormLimits {
+    fn default() -> Self {
+        Self {
+            upload_dir: std::env::temp_dir(),
+            max_size: 256 * 1024 * 1024,
+            max_file_size: 64 * 1024 * 1024,
+            max_files: 16,
+            max_fields: 64,
+            max_field_size: 64 * 1024,
+        }
+    }
+}
+
+// Fields of an urlencoded or multipart body, text fields share the query string accessors
+#[derive(Debug, Clone, Default)]
+pub struct Form {
+    fields: QueryMap,
+    files: Vec<UploadedFile>,
+}
+
+impl Form {
+    pub fn fields(&self) -> &QueryMap {
+        &self.fields
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.get(name)
+    }
+
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files.iter().find(|file| file.name == name)
+    }
+
+    pub fn files(&self) -> &[UploadedFile] {
+        &self.files
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn deserialize<T: serde::de::DeserializeOwned>(&self) -> ServerResult<T> {
+        self.fields.deserialize()
+    }
+}
+
+// A file part of a multipart body, the temporary file is removed once the last copy of the
+// request is dropped unless it was persisted. The file name is the one sent by the client with
+// any directory stripped, it should not be trusted as a path
+#[derive(Debug, Clone)]
+pub struct UploadedFile {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub size: u64,
+    temp: Arc<TempFile>,
+}
+
+#[derive(Debug)]
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+impl UploadedFile {
+    pub fn path(&self) -> &Path {
+        &self.temp.0
+    }
+
+    // Moves the upload out of the upload directory, copying it when they are on different devices
+    pub fn persist(&self, destination: impl AsRef<Path>) -> io::Result<()> {
+        if fs::rename(self.path(), &destination).is_ok() {
+            return Ok(());
+        }
+        fs::copy(self.path(), destination)?;
+        Ok(())
+    }
+}
+
+pub fn parse_urlencoded(body: &[u8]) -> Form {
+    Form {
+        fields: QueryMap::parse(&String::from_utf8_lossy(body)),
+        files: vec![],
+    }
+}
+
+pub async fn parse_multipart<'r, S>(
+    body: S,
+    boundary: String,
+    limits: &FormLimits,
+) -> ServerResult<Form>
+where
+    S: Stream<Item = io::Result<Vec<u8>>> + Send + 'r,
+{
+    let constraints = Constraints::new().size_limit(SizeLimit::new().whole_stream(limits.max_size));
+    let mut multipart = Multipart::with_constraints(body, boundary, constraints);
+    let mut fields = vec![];
+    let mut files = vec![];
+    while let Some(mut field) = multipart.next_field().await.map_err(multipart_error)? {
+        let name = field.name().unwrap_or_default().to_string();
+        let filename = match field.file_name() {
+            // Browsers send an empty file part for file inputs left empty
+            Some("") => continue,
+            Some(filename) => filename.rsplit(['/', '\\']).next().map(str::to_string),
+            None => {
+                if fields.len() >= limits.max_fields {
+                    return Err(too_large("Too many form fields"));
+                }
+                let mut value = vec![];
+                while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+                    if value.len() + chunk.len() > limits.max_field_size {
+                        return Err(too_large(&format!("Form field too large: {}", name)));
+                    }
+                    value.extend_from_slice(&chunk);
+                }
+                let value = String::from_utf8(value).map_err(|_| {
+                    ServerError::new(
+                        StatusCode::BAD_REQUEST,
+                        &format!("Invalid form field: {}", name),
+                    )
+                })?;
+                fields.push((name, value));
+                continue;
+            }
+        };
+        if files.len() >= limits.max_files {
+            return Err(too_large("Too many uploaded files"));
+        }
+
+        let content_type = field.content_type().map(|mime| mime.to_string());
+        let temp = TempFile(upload_path(&limits.upload_dir));
+        let write_error =
+            |e: io::Error| ServerError::err(&format!("Error writing upload: {}", e)).log();
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp.0)
+            .await
+            .map_err(write_error)?;
+        let mut size = 0;
+        while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+            size += chunk.len() as u64;
+            if size > limits.max_file_size {
+                return Err(too_large(&format!("Uploaded file too large: {}", name)));
+            }
+            file.write_all(&chunk).await.map_err(write_error)?;
+        }
+        file.flush().await.map_err(write_error)?;
+        files.push(UploadedFile {
+            name,
+            filename,
+            content_type,
+            size,
+            temp: Arc::new(temp),
+        });
+    }
+    Ok(Form {
+        fields: QueryMap::from_pairs(fields),
+        files,
+    })
+}
+
+fn upload_path(dir: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    dir.join(format!("upload-{:016x}", hasher.finish()))
+}
+
+// Bodies whose chunk sizes exceed the limit fail while they are read, like the ones multer stops
+fn multipart_error(error: multer::Error) -> ServerError {
+    let read_error = match &error {
+        multer::Error::StreamReadFailed(e) => e.downcast_ref::<io::Error>(),
+        _ => None,
+    };
+    match error {
+        _ if read_error.is_some_and(|e| e.kind() == io::ErrorKind::FileTooLarge) => {
+            too_large("Request body too large")
+        }
+        multer::Error::StreamSizeExceeded { .. } => too_large("Request body too large"),
+        error => ServerError::new(
+            StatusCode::BAD_REQUEST,
+            &format!("Invalid multipart body: {}", error),
+        ),
+    }
+}
+
+fn too_large(message: &str) -> ServerError {
+    ServerError::new(StatusCode::PAYLOAD_TOO_LARGE, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::stream;
+
+    use super::*;
+
+    const BOUNDARY: &str = "form-boundary";
+
+    // Parts are (name, file name, content)
+    fn multipart(parts: &[(&str, Option<&str>, &str)]) -> Vec<u8> {
+        let mut body = String::new();
+        for (name, filename, content) in parts {
+            body.push_str(&format!("--{}\r\n", BOUNDARY));
+            match filename {
+                Some(filename) => body.push_str(&format!(
+                    "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: image/jpeg\r\n",
+                    name, filename
+                )),
+                None => body.push_str(&format!(
+                    "Content-Disposition: form-data; name=\"{}\"\r\n",
+                    name
+                )),
+            }
+            body.push_str(&format!("\r\n{}\r\n", content));
+        }
+        body.push_str(&format!("--{}--\r\n", BOUNDARY));
+        body.into_bytes()
+    }
+
+    // Each test uploads to its own directory, to check what is left in it
+    fn limits(name: &str) -> FormLimits {
+        let upload_dir = std::env::temp_dir().join(format!("form-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&upload_dir).unwrap();
+        FormLimits {
+            upload_dir,
+            ..Default::default()
+        }
+    }
+
+    fn parse(body: Vec<u8>, limits: &FormLimits) -> ServerResult<Form> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        // Small chunks, so parts span several of them
+        let chunks = body.chunks(7).map(|c| Ok(c.to_vec())).collect::<Vec<_>>();
+        runtime.block_on(parse_multipart(
+            stream::iter(chunks),
+            BOUNDARY.to_string(),
+            limits,
+        ))
+    }
+
+    fn uploads(limits: &FormLimits) -> usize {
+        fs::read_dir(&limits.upload_dir).unwrap().count()
+    }
+
+    #[test]
+    fn parses_fields_and_files() {
+        let limits = limits("parse");
+        let body = multipart(&[
+            ("title", None, "Holiday"),
+            ("tag", None, "sea"),
+            ("tag", None, "sun"),
+            ("photo", Some("C:\\Users\\me\\beach.jpg"), "jpeg data"),
+            ("other", Some("../../etc/passwd"), "x"),
+            ("empty", Some(""), ""),
+        ]);
+        let form = parse(body, &limits).unwrap();
+        assert_eq!(form.get("title"), Some("Holiday"));
+        assert_eq!(
+            form.fields().get_all("tag").collect::<Vec<_>>(),
+            ["sea", "sun"]
+        );
+        assert_eq!(form.files().len(), 2);
+        let photo = form.file("photo").unwrap();
+        assert_eq!(photo.filename.as_deref(), Some("beach.jpg"));
+        assert_eq!(photo.content_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(photo.size, 9);
+        assert_eq!(fs::read_to_string(photo.path()).unwrap(), "jpeg data");
+        assert!(photo.path().starts_with(&limits.upload_dir));
+        let other = form.file("other").unwrap();
+        assert_eq!(other.filename.as_deref(), Some("passwd"));
+        assert!(form.file("empty").is_none());
+        drop(form);
+        fs::remove_dir_all(&limits.upload_dir).unwrap();
+    }
+
+    #[test]
+    fn removes_uploads_with_the_last_copy() {
+        let limits = limits("cleanup");
+        let form = parse(multipart(&[("a", Some("a.txt"), "a")]), &limits).unwrap();
+        let copy = form.clone();
+        let path = form.file("a").unwrap().path().to_path_buf();
+        drop(form);
+        assert!(path.exists());
+        drop(copy);
+        assert!(!path.exists());
+
+        let form = parse(multipart(&[("b", Some("b.txt"), "b")]), &limits).unwrap();
+        let destination = limits.upload_dir.join("kept.txt");
+        form.file("b").unwrap().persist(&destination).unwrap();
+        drop(form);
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "b");
+        assert_eq!(uploads(&limits), 1);
+        fs::remove_dir_all(&limits.upload_dir).unwrap();
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let base = limits("limits");
+        let too_large = |limits: FormLimits, body: Vec<u8>| {
+            let error = parse(body, &limits).unwrap_err();
+            assert_eq!(error.code(), StatusCode::PAYLOAD_TOO_LARGE);
+            error.public_message().to_string()
+        };
+        let fields = multipart(&[("a", None, "1"), ("b", None, "2")]);
+        let limits = FormLimits {
+            max_fields: 1,
+            ..base.clone()
+        };
+        assert_eq!(too_large(limits, fields.clone()), "Too many form fields");
+        let limits = FormLimits {
+            max_field_size: 4,
+            ..base.clone()
+        };
+        let field = multipart(&[("a", None, "12345")]);
+        assert_eq!(too_large(limits, field), "Form field too large: a");
+
+        let files = multipart(&[("a", Some("a"), "1"), ("b", Some("b"), "2")]);
+        let limits = FormLimits {
+            max_files: 1,
+            ..base.clone()
+        };
+        assert_eq!(too_large(limits, files), "Too many uploaded files");
+        let limits = FormLimits {
+            max_file_size: 4,
+            ..base.clone()
+        };
+        let file = multipart(&[("a", Some("a"), "12345")]);
+        assert_eq!(too_large(limits, file), "Uploaded file too large: a");
+        let limits = FormLimits {
+            max_size: 64,
+            ..base.clone()
+        };
+        let body = multipart(&[("a", Some("a"), &"x".repeat(100))]);
+        assert_eq!(too_large(limits, body), "Request body too large");
+
+        // Files written before the limit was hit are removed with the failed form
+        assert_eq!(uploads(&base), 0);
+        fs::remove_dir_all(&base.upload_dir).unwrap();
+    }
+
+    #[test]
+    fn parses_urlencoded_bodies() {
+        let form = parse_urlencoded(b"name=Living+room&tag=a%26b&tag=c");
+        assert_eq!(form.get("name"), Some("Living room"));
+        assert_eq!(
+            form.fields().get_all("tag").collect::<Vec<_>>(),
+            ["a&b", "c"]
+        );
+        assert!(form.files().is_empty());
+    }
+}
@@ -0,0 +1,197 @@
+use std::{collections::HashMap, path::Path, sync::OnceLock};
+
+// Whether a browser should render the file or save it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    Inline,
+    Attachment,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MimeType {
+    pub content_type: String,
+    pub disposition: Disposition,
+}
+
+impl MimeType {
+    pub fn new(content_type: &str, disposition: Disposition) -> Self {
+        Self {
+            content_type: content_type.to_string(),
+            disposition,
+        }
+    }
+
+    pub fn octet_stream() -> Self {
+        Self::new("application/octet-stream", Disposition::Attachment)
+    }
+}
+
+// Extension table used to pick the content type of served files, extensions are stored lowercase
+// and without the leading dot
+#[derive(Debug, Clone)]
+pub struct MimeTypes {
+    types: HashMap<String, MimeType>,
+}
+
+const INLINE_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("xml", "application/xml"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("bmp", "image/bmp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("webm", "video/webm"),
+    ("mkv", "video/x-matroska"),
+    ("mov", "video/quicktime"),
+    ("mp3", "audio/mpeg"),
+    ("m4a", "audio/mp4"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("ogv", "video/ogg"),
+    ("opus", "audio/opus"),
+    ("wav", "audio/wav"),
+    ("flac", "audio/flac"),
+    ("pdf", "application/pdf"),
+    ("wasm", "application/wasm"),
+];
+
+const ATTACHMENT_TYPES: &[(&str, &str)] = &[
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("7z", "application/x-7z-compressed"),
+    ("bin", "application/octet-stream"),
+    ("exe", "application/octet-stream"),
+    ("iso", "application/octet-stream"),
+];
+
+impl Default for MimeTypes {
+    fn default() -> Self {
+        let mut types = Self::empty();
+        for (extension, content_type) in INLINE_TYPES {
+            types.insert(extension, content_type, Disposition::Inline);
+        }
+        for (extension, content_type) in ATTACHMENT_TYPES {
+            types.insert(extension, content_type, Disposition::Attachment);
+        }
+        types
+    }
+}
+
+impl MimeTypes {
+    pub fn empty() -> Self {
+        Self {
+            types: HashMap::new(),
+        }
+    }
+
+    // Shared default table, used when serving files outside of a router
+    pub fn standard() -> &'static MimeTypes {
+        static STANDARD: OnceLock<MimeTypes> = OnceLock::new();
+        STANDARD.get_or_init(MimeTypes::default)
+    }
+
+    pub fn insert(
+        &mut self,
+        extension: &str,
+        content_type: &str,
+        disposition: Disposition,
+    ) -> &mut Self {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        self.types
+            .insert(extension, MimeType::new(content_type, disposition));
+        self
+    }
+
+    pub fn from_extension(&self, path: &Path) -> Option<&MimeType> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        self.types.get(&extension)
+    }
+
+    // Extension table first, then the first bytes of the file, anything unknown is downloaded
+    pub fn guess(&self, path: &Path, head: &[u8]) -> MimeType {
+        match self.from_extension(path) {
+            Some(mime) => mime.clone(),
+            None => sniff(head).unwrap_or_else(MimeType::octet_stream),
+        }
+    }
+}
+
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"\x1a\x45\xdf\xa3", "video/webm"),
+    (b"OggS", "audio/ogg"),
+    (b"fLaC", "audio/flac"),
+    (b"ID3", "audio/mpeg"),
+    (b"\x00asm", "application/wasm"),
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+];
+
+// Content sniffing based on magic numbers, only used when the extension is unknown
+pub fn sniff(head: &[u8]) -> Option<MimeType> {
+    for (signature, content_type) in SIGNATURES {
+        if head.starts_with(signature) {
+            return Some(MimeType::new(content_type, Disposition::Inline));
+        }
+    }
+    if head.len() >= 12 && &head[0..4] == b"RIFF" {
+        match &head[8..12] {
+            b"WEBP" => return Some(MimeType::new("image/webp", Disposition::Inline)),
+            b"WAVE" => return Some(MimeType::new("audio/wav", Disposition::Inline)),
+            _ => {}
+        }
+    }
+    if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        return Some(MimeType::new("video/mp4", Disposition::Inline));
+    }
+
+    // The head may cut a multibyte character in half, that still counts as text
+    let valid = match std::str::from_utf8(head) {
+        Ok(text) => text.len(),
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => return None,
+    };
+    let text = std::str::from_utf8(&head[..valid]).ok()?;
+    if text.chars().any(|c| c.is_control() && !c.is_whitespace()) {
+        return None;
+    }
+    let start = text.trim_start().to_lowercase();
+    let content_type = if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        "text/html; charset=utf-8"
+    } else if start.starts_with("<svg") {
+        "image/svg+xml"
+    } else if start.starts_with("<?xml") {
+        "application/xml"
+    } else if start.starts_with('{') || start.starts_with('[') {
+        "application/json"
+    } else {
+        "text/plain; charset=utf-8"
+    };
+    Some(MimeType::new(content_type, Disposition::Inline))
+}
@@ -1,18 +1,27 @@
 pub mod auth;
+pub mod compression;
+pub mod conditional;
 pub mod connection;
+pub mod cors;
+pub mod error_page;
+pub mod form;
+pub mod mime;
+pub mod query;
+pub mod range;
 pub mod request;
 pub mod response;
 pub mod router;
 pub mod server;
 
 use crate::common::log::{log_message, LogLevel};
-use http::StatusCode;
+use http::{HeaderName, HeaderValue, StatusCode};
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct ServerError {
     code: StatusCode,
     error: String,
+    headers: Vec<(HeaderName, HeaderValue)>,
 }
 
 impl ServerError {
@@ -20,6 +29,7 @@ impl ServerError {
         ServerError {
             code,
             error: error.to_string(),
+            headers: vec![],
         }
     }
 
@@ -27,6 +37,32 @@ impl ServerError {
         ServerError::new(StatusCode::INTERNAL_SERVER_ERROR, error)
     }
 
+    // Header sent along with the error response, whatever renders it
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    pub fn code(&self) -> StatusCode {
+        self.code
+    }
+
+    // Message that can be shown to the client, internal errors only give their status so their
+    // details stay in the log
+    pub fn public_message(&self) -> &str {
+        match self.code.is_server_error() {
+            true => self
+                .code
+                .canonical_reason()
+                .unwrap_or("Internal Server Error"),
+            false => &self.error,
+        }
+    }
+
+    pub fn headers(&self) -> &[(HeaderName, HeaderValue)] {
+        &self.headers
+    }
+
     pub fn log(self) -> Self {
         log_message(LogLevel::Error, &self.error);
         self
@@ -0,0 +1,113 @@
+use std::str::FromStr;
+
+use http::StatusCode;
+
+use super::{ServerError, ServerResult};
+
+// Decoded query string of a request, parsed once when the request is read. Pairs keep the order
+// they were sent in and keys may repeat (?tag=a&tag=b)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryMap {
+    raw: String,
+    pairs: Vec<(String, String)>,
+}
+
+impl QueryMap {
+    pub fn parse(query: &str) -> Self {
+        let pairs = form_urlencoded::parse(query.as_bytes())
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+        Self {
+            raw: query.to_string(),
+            pairs,
+        }
+    }
+
+    // Builds the map from decoded pairs, the raw string is encoded back from them
+    pub fn from_pairs(pairs: Vec<(String, String)>) -> Self {
+        let raw = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(&pairs)
+            .finish();
+        Self { raw, pairs }
+    }
+
+    // The query string as it was received, still encoded
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(k, _)| k == key)
+    }
+
+    // First value sent for the key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.pairs
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    // Parses a required argument, missing or malformed values are a 400 for the client
+    pub fn parse_value<T: FromStr>(&self, key: &str) -> ServerResult<T> {
+        self.parse_optional(key)?
+            .ok_or_else(|| missing_argument("query", key))
+    }
+
+    pub fn parse_optional<T: FromStr>(&self, key: &str) -> ServerResult<Option<T>> {
+        self.get(key)
+            .map(|value| parse_argument("query", key, value))
+            .transpose()
+    }
+
+    // Every value sent for the key, an absent key is an empty list
+    pub fn parse_all<T: FromStr>(&self, key: &str) -> ServerResult<Vec<T>> {
+        self.get_all(key)
+            .map(|value| parse_argument("query", key, value))
+            .collect()
+    }
+
+    // Deserializes the whole query into a struct, repeated keys map to sequences
+    #[cfg(feature = "serde")]
+    pub fn deserialize<T: serde::de::DeserializeOwned>(&self) -> ServerResult<T> {
+        serde_html_form::from_str(&self.raw).map_err(|e| {
+            ServerError::new(
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid query string: {}", e),
+            )
+        })
+    }
+}
+
+// Arguments are parsed the same way whether they come from the query or the path, the source
+// names where the argument was looked for in the error sent to the client
+pub fn parse_argument<T: FromStr>(source: &str, name: &str, value: &str) -> ServerResult<T> {
+    value.parse().map_err(|_| {
+        ServerError::new(
+            StatusCode::BAD_REQUEST,
+            &format!("Invalid {} argument: {}", source, name),
+        )
+    })
+}
+
+pub fn missing_argument(source: &str, name: &str) -> ServerError {
+    ServerError::new(
+        StatusCode::BAD_REQUEST,
+        &format!("Missing {} argument: {}", source, name),
+    )
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    ops::Range,
+};
+
+use futures_util::{stream, StreamExt};
+use http::{
+    header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, LAST_MODIFIED},
+    HeaderValue, Method, StatusCode,
+};
+
+use super::{
+    request::RequestHead,
+    response::{FileBody, IntoResponse, ResponseBody, ServerResponse},
+    ServerError, ServerResult,
+};
+
+// Requests asking for more ranges than this get the whole file instead
+const MAX_RANGES: usize = 16;
+
+// Answers Range requests for file responses, anything that is not a complete file sent with 200 is
+// passed through untouched
+pub fn apply(request: &RequestHead, mut response: ServerResponse) -> ServerResult<ServerResponse> {
+    if response.status() != StatusCode::OK {
+        return Ok(response);
+    }
+    let size = match response.body() {
+        Some(ResponseBody::File(file)) => file.len,
+        _ => return Ok(response),
+    };
+    response
+        .headers_mut()
+        .insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    if request.method != Method::GET {
+        return Ok(response);
+    }
+    let header = match request.header("Range") {
+        Some(header) => header,
+        None => return Ok(response),
+    };
+    if let Some(validator) = request.header("If-Range") {
+        if !matches_validator(&response, validator) {
+            return Ok(response);
+        }
+    }
+    let ranges = match parse_ranges(header, size) {
+        Some(ranges) if ranges.len() <= MAX_RANGES => ranges,
+        _ => return Ok(response),
+    };
+    if ranges.is_empty() {
+        let mut response =
+            ServerResponse::create(StatusCode::RANGE_NOT_SATISFIABLE, "Range not satisfiable");
+        let value = HeaderValue::from_str(&format!("bytes */{}", size)).unwrap();
+        response.headers_mut().insert(CONTENT_RANGE, value);
+        return Ok(response);
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let file = match body {
+        Some(ResponseBody::File(file)) => file,
+        _ => unreachable!(),
+    };
+    parts.status = StatusCode::PARTIAL_CONTENT;
+    parts.headers.remove(CONTENT_LENGTH);
+    let body = if let [range] = ranges.as_slice() {
+        parts
+            .headers
+            .insert(CONTENT_RANGE, content_range(range, size));
+        slice(&file, range)?
+    } else {
+        let content_type = parts.headers.remove(CONTENT_TYPE);
+        let boundary = boundary();
+        let value = format!("multipart/byteranges; boundary={}", boundary);
+        parts
+            .headers
+            .insert(CONTENT_TYPE, HeaderValue::from_str(&value).unwrap());
+        multipart(&file, &ranges, content_type, &boundary)?
+    };
+    Ok(ServerResponse::from_parts(parts, Some(body)))
+}
+
+// If-Range holds either an entity tag or a date, the range is only honored when it matches the
+// current representation exactly
+fn matches_validator(response: &ServerResponse, validator: &str) -> bool {
+    let validator = validator.trim();
+    if validator.starts_with("W/") {
+        return false;
+    }
+    let header = if validator.starts_with('"') {
+        ETAG
+    } else {
+        LAST_MODIFIED
+    };
+    match response.headers().get(header) {
+        Some(current) => current.as_bytes() == validator.as_bytes(),
+        None => false,
+    }
+}
+
+// Returns None when the header can't be parsed (so it is ignored) and an empty list when none of
+// the ranges can be satisfied
+fn parse_ranges(header: &str, size: u64) -> Option<Vec<Range<u64>>> {
+    let specs = header.trim().strip_prefix("bytes=")?;
+    let mut ranges = vec![];
+    for spec in specs.split(',') {
+        let (start, end) = spec.trim().split_once('-')?;
+        let range = match (start.trim(), end.trim()) {
+            ("", "") => return None,
+            ("", suffix) => {
+                let suffix = suffix.parse::<u64>().ok()?;
+                size.saturating_sub(suffix)..size
+            }
+            (start, "") => start.parse::<u64>().ok()?..size,
+            (start, end) => {
+                let (start, end) = (start.parse::<u64>().ok()?, end.parse::<u64>().ok()?);
+                if end < start {
+                    return None;
+                }
+                start..end.saturating_add(1).min(size)
+            }
+        };
+        if range.start < size && !range.is_empty() {
+            ranges.push(range);
+        }
+    }
+    Some(ranges)
+}
+
+fn content_range(range: &Range<u64>, size: u64) -> HeaderValue {
+    let value = format!("bytes {}-{}/{}", range.start, range.end - 1, size);
+    HeaderValue::from_str(&value).unwrap()
+}
+
+fn slice(file: &FileBody, range: &Range<u64>) -> ServerResult<ResponseBody> {
+    let handle = file
+        .file
+        .try_clone()
+        .map_err(|e| ServerError::err(&format!("Error reading file: {}", e)))?;
+    Ok(ResponseBody::File(FileBody {
+        file: handle,
+        path: file.path.clone(),
+        start: file.start + range.start,
+        len: range.end - range.start,
+    }))
+}
+
+fn multipart(
+    file: &FileBody,
+    ranges: &[Range<u64>],
+    content_type: Option<HeaderValue>,
+    boundary: &str,
+) -> ServerResult<ResponseBody> {
+    let content_type = content_type
+        .as_ref()
+        .and_then(|v| v.to_str().ok())
+        .map(|v| format!("Content-Type: {}\r\n", v))
+        .unwrap_or_default();
+    let mut parts = vec![];
+    for range in ranges {
+        let head = format!(
+            "\r\n--{}\r\n{}Content-Range: bytes {}-{}/{}\r\n\r\n",
+            boundary,
+            content_type,
+            range.start,
+            range.end - 1,
+            file.len
+        );
+        parts.push(ResponseBody::Bytes(head.into_bytes()).into_stream());
+        parts.push(slice(file, range)?.into_stream());
+    }
+    let tail = format!("\r\n--{}--\r\n", boundary).into_bytes();
+    parts.push(ResponseBody::Bytes(tail).into_stream());
+    Ok(ResponseBody::Stream(Box::pin(
+        stream::iter(parts).flatten(),
+    )))
+}
+
+fn boundary() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ranges as (start, end) pairs, end excluded
+    fn ranges(header: &str, size: u64) -> Option<Vec<(u64, u64)>> {
+        let ranges = parse_ranges(header, size)?;
+        Some(ranges.iter().map(|r| (r.start, r.end)).collect())
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(ranges("bytes=0-99", 1000), Some(vec![(0, 100)]));
+        assert_eq!(ranges("bytes=900-", 1000), Some(vec![(900, 1000)]));
+        assert_eq!(ranges("bytes=-100", 1000), Some(vec![(900, 1000)]));
+        assert_eq!(
+            ranges("bytes=0-0, 10-19", 1000),
+            Some(vec![(0, 1), (10, 20)])
+        );
+    }
+
+    #[test]
+    fn clamps_ranges_to_size() {
+        assert_eq!(ranges("bytes=500-5000", 1000), Some(vec![(500, 1000)]));
+        assert_eq!(ranges("bytes=-5000", 1000), Some(vec![(0, 1000)]));
+        assert_eq!(
+            ranges("bytes=0-18446744073709551615", 1000),
+            Some(vec![(0, 1000)])
+        );
+    }
+
+    #[test]
+    fn unsatisfiable_ranges_are_empty() {
+        assert_eq!(ranges("bytes=1000-", 1000), Some(vec![]));
+        assert_eq!(ranges("bytes=-0", 1000), Some(vec![]));
+    }
+
+    #[test]
+    fn invalid_ranges_are_ignored() {
+        assert_eq!(ranges("items=0-1", 1000), None);
+        assert_eq!(ranges("bytes=5-1", 1000), None);
+        assert_eq!(ranges("bytes=-", 1000), None);
+        assert_eq!(ranges("bytes=a-b", 1000), None);
+    }
+}
@@ -1,47 +1,158 @@
+use std::{pin::Pin, sync::Arc};
+
 use crate::server::{ServerError, ServerResult};
-use http::{Method, Request};
-use tokio::io::{AsyncBufReadExt, BufReader};
+use async_compression::futures::bufread::{BrotliDecoder, GzipDecoder, ZlibDecoder, ZstdDecoder};
+use futures_util::{
+    io::{AsyncRead, AsyncReadExt as _},
+    stream, Stream, TryStreamExt,
+};
+use http::{
+    header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING},
+    HeaderMap, HeaderValue, Method, Request, StatusCode,
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, BufReader},
+    net::TcpStream,
+};
+use tokio_rustls::server::TlsStream;
+
+use super::{
+    connection::Connection,
+    form::{self, Form, FormLimits},
+    query::{self, QueryMap},
+    router::router::RouteTable,
+};
 
-use super::connection::Connection;
+// Size of the chunks read from the connection while streaming a multipart body
+const CHUNK_SIZE: usize = 64 * 1024;
 
-pub type RequestBody = Option<String>;
+pub type RequestBody = Option<Vec<u8>>;
 
+// The query string and form bodies are parsed once when the request is built, multipart bodies
+// are consumed while parsing so the request has no raw body for them
 #[derive(Debug, Clone)]
-pub struct ServerRequest(Request<RequestBody>);
+pub struct ServerRequest {
+    request: Request<RequestBody>,
+    query: QueryMap,
+    form: Option<Form>,
+    routes: Arc<RouteTable>,
+}
 
 impl ServerRequest {
+    pub fn new(request: Request<RequestBody>) -> Self {
+        let query = QueryMap::parse(request.uri().query().unwrap_or_default());
+        let form = match (content_type(request.headers()), request.body()) {
+            (Some("application/x-www-form-urlencoded"), Some(body)) => {
+                Some(form::parse_urlencoded(body))
+            }
+            _ => None,
+        };
+        Self {
+            request,
+            query,
+            form,
+            routes: Arc::default(),
+        }
+    }
+
     pub fn method(&self) -> &Method {
-        self.0.method()
+        self.request.method()
     }
 
     pub fn path(&self) -> &str {
-        self.0.uri().path()
+        self.request.uri().path()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.request
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+    }
+
+    pub fn head(&self) -> RequestHead {
+        RequestHead {
+            method: self.method().clone(),
+            headers: self.request.headers().clone(),
+        }
+    }
+
+    pub fn body_bytes(&self) -> &[u8] {
+        self.request.body().as_deref().unwrap_or_default()
     }
 
     pub fn body_str(&self) -> String {
-        self.0.body().clone().unwrap().into()
+        String::from_utf8_lossy(self.body_bytes()).into_owned()
     }
 
-    pub fn query_argument(&self, key: &str) -> ServerResult<&str> {
-        match self.0.uri().query() {
-            Some(query) => {
-                let re = regex::Regex::new(&format!(r"{}=([^&]+)", key)).unwrap();
-                match re.captures(query) {
-                    Some(caps) => Ok(caps.get(1).unwrap().as_str()),
-                    None => Err(ServerError::err("No match")),
-                }
-            }
-            None => Err(ServerError::err("No query string")),
+    pub fn query(&self) -> &QueryMap {
+        &self.query
+    }
+
+    pub fn form(&self) -> ServerResult<&Form> {
+        self.form.as_ref().ok_or(ServerError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected a form body",
+        ))
+    }
+
+    // Deserializes a JSON body, malformed JSON is a 400 and JSON that doesn't fit the type a 422
+    #[cfg(feature = "json")]
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> ServerResult<T> {
+        let is_json = content_type(self.request.headers())
+            .is_some_and(|t| t == "application/json" || t.ends_with("+json"));
+        if !is_json {
+            return Err(ServerError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected a JSON body",
+            ));
         }
+        serde_json::from_slice(self.body_bytes()).map_err(|e| {
+            let code = match e.classify() {
+                serde_json::error::Category::Data => StatusCode::UNPROCESSABLE_ENTITY,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            ServerError::new(code, &format!("Invalid JSON body: {}", e))
+        })
+    }
+
+    // Link to a named route of the router that resolved the request
+    pub fn url_for<K: AsRef<str>, V: ToString>(
+        &self,
+        name: &str,
+        args: impl IntoIterator<Item = (K, V)>,
+    ) -> ServerResult<String> {
+        self.routes.url_for(name, args)
+    }
+
+    // Routes of the router that resolved the request
+    pub fn routes(&self) -> &RouteTable {
+        &self.routes
+    }
+
+    pub(crate) fn set_route_table(&mut self, routes: Arc<RouteTable>) {
+        self.routes = routes;
+    }
+
+    pub fn query_argument(&self, key: &str) -> ServerResult<&str> {
+        self.query
+            .get(key)
+            .ok_or_else(|| query::missing_argument("query", key))
     }
 
-    pub async fn from_connection(connection: &mut Connection) -> ServerResult<Self> {
-        let reader = BufReader::new(&mut connection.stream);
-        let mut lines = reader.lines();
+    // The head of the request is handed to authorize before the body is read, so bodies and
+    // uploads of requests that won't be served are never read or spooled to disk
+    pub async fn from_connection(
+        connection: &mut Connection,
+        limits: &BodyLimits,
+        form_limits: &FormLimits,
+        authorize: impl FnOnce(&ServerRequest) -> ServerResult<()>,
+    ) -> ServerResult<Self> {
+        let mut reader = BufReader::new(&mut connection.stream);
         let mut builder = http::Request::builder();
         let has_body: bool;
 
-        let first_line = match lines.next_line().await {
+        let first_line = match next_line(&mut reader).await {
             Ok(line) => line,
             Err(e) => {
                 return Err(ServerError::new(
@@ -51,8 +162,8 @@ impl ServerRequest {
             }
         };
 
-        let re_head = regex::Regex::new(r"^(GET|POST|PUT|DELETE) (.+) HTTP/1\.1$").unwrap();
-        match re_head.captures(&first_line.unwrap()) {
+        let re_head = regex::Regex::new(r"^(GET|POST|PUT|DELETE|OPTIONS) (.+) HTTP/1\.1$").unwrap();
+        match re_head.captures(&first_line.unwrap_or_default()) {
             Some(caps) => {
                 let method = match caps.get(1) {
                     Some(m) => m.as_str(),
@@ -71,7 +182,7 @@ impl ServerRequest {
 
         let re_header = regex::Regex::new(r"^([\w-]+): (.+)$").unwrap();
         loop {
-            let line = match lines.next_line().await {
+            let line = match next_line(&mut reader).await {
                 Ok(Some(line)) => line,
                 Ok(None) => {
                     if has_body {
@@ -99,21 +210,461 @@ impl ServerRequest {
                 None => return Err(ServerError::err("Invalid header")),
             }
         }
-        let mut body = String::new();
-        if has_body {
-            loop {
-                match lines.next_line().await {
-                    Ok(Some(line)) => body.push_str(&line),
-                    Ok(None) => break,
-                    _ => return Err(ServerError::err("Error reading body")),
-                };
+
+        let mut request = builder
+            .body(None)
+            .map_err(|_| ServerError::new(http::StatusCode::BAD_REQUEST, "Invalid request"))?;
+        authorize(&Self::new(request.clone()))?;
+
+        let mut form = None;
+        let boundary = content_type(request.headers())
+            .filter(|content_type| *content_type == "multipart/form-data")
+            .and_then(|_| {
+                let value = request.headers().get(CONTENT_TYPE)?.to_str().ok()?;
+                multer::parse_boundary(value).ok()
+            });
+        if let (true, Some(boundary)) = (has_body, boundary) {
+            let headers = request.headers();
+            form = Some(read_multipart(&mut reader, headers, boundary, form_limits).await?);
+        } else if has_body {
+            let headers = request.headers_mut();
+            let raw = read_body(&mut reader, headers, limits.max_body_size).await?;
+            let encoding = content_encoding(headers);
+            headers.remove(CONTENT_ENCODING);
+            let decoded = match encoding.as_deref() {
+                None | Some("identity") => raw,
+                Some(encoding) => decode_body(raw, encoding, limits.max_decoded_size).await?,
+            };
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(decoded.len()));
+            headers.remove(TRANSFER_ENCODING);
+            *request.body_mut() = Some(decoded);
+        }
+
+        let mut request = Self::new(request);
+        if form.is_some() {
+            request.form = form;
+        }
+        Ok(request)
+    }
+}
+
+// Method and headers of a request, kept for the steps that rework its response once the request
+// itself was handed to the router
+#[derive(Debug, Clone)]
+pub struct RequestHead {
+    pub method: Method,
+    pub headers: HeaderMap,
+}
+
+impl RequestHead {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+}
+
+// Limits applied while reading request bodies, the decoded size is checked separately so small
+// compressed payloads can't expand into huge ones
+#[derive(Debug, Clone)]
+pub struct BodyLimits {
+    pub max_body_size: usize,
+    pub max_decoded_size: usize,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        Self {
+            max_body_size: 16 * 1024 * 1024,
+            max_decoded_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+type ConnectionReader<'a> = BufReader<&'a mut TlsStream<TcpStream>>;
+
+async fn next_line(reader: &mut (impl AsyncBufRead + Unpin)) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+async fn read_body(
+    reader: &mut ConnectionReader<'_>,
+    headers: &HeaderMap,
+    limit: usize,
+) -> ServerResult<Vec<u8>> {
+    if is_chunked(headers) {
+        return read_chunked(reader, limit).await;
+    }
+    let length = match headers.get(CONTENT_LENGTH) {
+        Some(length) => length
+            .to_str()
+            .ok()
+            .and_then(|l| l.trim().parse::<usize>().ok())
+            .ok_or(ServerError::new(
+                StatusCode::BAD_REQUEST,
+                "Invalid Content-Length",
+            ))?,
+        None => 0,
+    };
+    if length > limit {
+        return Err(too_large());
+    }
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .await
+        .map_err(|_| ServerError::new(StatusCode::BAD_REQUEST, "Error reading body"))?;
+    Ok(body)
+}
+
+// Multipart bodies are parsed while they are read and decoded whatever their transfer and content
+// encoding, so they are only bounded by the form limits and never held in memory
+async fn read_multipart(
+    reader: &mut (impl AsyncBufRead + Unpin + Send),
+    headers: &HeaderMap,
+    boundary: String,
+    form_limits: &FormLimits,
+) -> ServerResult<Form> {
+    let body: RawBody<'_> = match is_chunked(headers) {
+        true => Box::pin(chunked_stream(reader, form_limits.max_size)),
+        false => {
+            let length = headers
+                .get(CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .ok_or(ServerError::new(
+                    StatusCode::LENGTH_REQUIRED,
+                    "Missing Content-Length",
+                ))?;
+            if length > form_limits.max_size {
+                return Err(too_large());
             }
+            Box::pin(length_stream(reader, length))
         }
+    };
+    let body = match content_encoding(headers).as_deref() {
+        None | Some("identity") => body,
+        Some(encoding) => Box::pin(read_stream(decoder(body.into_async_read(), encoding)?)),
+    };
+    form::parse_multipart(body, boundary, form_limits).await
+}
 
-        let request = builder
-            .body(if has_body { Some(body) } else { None })
+type RawBody<'a> = Pin<Box<dyn Stream<Item = std::io::Result<Vec<u8>>> + Send + 'a>>;
+
+// The next bytes of a body sent with a Content-Length
+fn length_stream(
+    reader: &mut (impl AsyncBufRead + Unpin + Send),
+    length: u64,
+) -> impl Stream<Item = std::io::Result<Vec<u8>>> + Send + '_ {
+    stream::try_unfold((reader, length), |(reader, remaining)| async move {
+        if remaining == 0 {
+            return Ok(None);
+        }
+        let mut chunk = vec![0; remaining.min(CHUNK_SIZE as u64) as usize];
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(ended_early());
+        }
+        chunk.truncate(read);
+        Ok(Some((chunk, (reader, remaining - read as u64))))
+    })
+}
+
+// The data of a chunked body as it arrives, chunks larger than CHUNK_SIZE are split. Chunk sizes
+// come from the client, the body fails with FileTooLarge as soon as they add up past the limit
+// instead of once the data was read. The state holds what is left of the current chunk, the size
+// of the body so far and whether a chunk was read before
+fn chunked_stream(
+    reader: &mut (impl AsyncBufRead + Unpin + Send),
+    limit: u64,
+) -> impl Stream<Item = std::io::Result<Vec<u8>>> + Send + '_ {
+    let error = |kind, message| std::io::Error::new(kind, message);
+    stream::try_unfold(
+        (reader, 0u64, 0u64, false),
+        move |(reader, mut remaining, mut size, started)| async move {
+            if remaining == 0 {
+                // The data of the previous chunk ends with a line break
+                if started {
+                    next_line(reader).await?;
+                }
+                let line = next_line(reader).await?.ok_or_else(ended_early)?;
+                let line = line.split(';').next().unwrap_or_default().trim();
+                remaining = u64::from_str_radix(line, 16)
+                    .map_err(|_| error(std::io::ErrorKind::InvalidData, "Invalid chunked body"))?;
+                if remaining == 0 {
+                    // Trailers are read and dropped
+                    while !next_line(reader).await?.unwrap_or_default().is_empty() {}
+                    return Ok(None);
+                }
+                if remaining > limit - size {
+                    return Err(error(
+                        std::io::ErrorKind::FileTooLarge,
+                        "Request body too large",
+                    ));
+                }
+                size += remaining;
+            }
+            let mut chunk = vec![0; remaining.min(CHUNK_SIZE as u64) as usize];
+            let read = reader.read(&mut chunk).await?;
+            if read == 0 {
+                return Err(ended_early());
+            }
+            chunk.truncate(read);
+            Ok(Some((chunk, (reader, remaining - read as u64, size, true))))
+        },
+    )
+}
+
+// Reads a decoded body in chunks
+fn read_stream<'a>(
+    reader: Pin<Box<dyn AsyncRead + Send + 'a>>,
+) -> impl Stream<Item = std::io::Result<Vec<u8>>> + Send + 'a {
+    stream::try_unfold(reader, |mut reader| async move {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        chunk.truncate(read);
+        Ok(Some((chunk, reader)))
+    })
+}
+
+fn ended_early() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Body ended early")
+}
+
+fn is_chunked(headers: &HeaderMap) -> bool {
+    headers
+        .get(TRANSFER_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.to_lowercase().contains("chunked"))
+}
+
+fn content_encoding(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(CONTENT_ENCODING)?;
+    Some(value.to_str().unwrap_or_default().trim().to_lowercase())
+}
+
+// Media type of the body without its parameters
+fn content_type(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    Some(value.split(';').next().unwrap_or_default().trim())
+}
+
+async fn read_chunked(
+    reader: &mut (impl AsyncBufRead + Unpin + Send),
+    limit: usize,
+) -> ServerResult<Vec<u8>> {
+    let mut body = vec![];
+    let mut chunks = std::pin::pin!(chunked_stream(reader, limit as u64));
+    while let Some(chunk) = chunks.try_next().await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::FileTooLarge => too_large(),
+        _ => ServerError::new(StatusCode::BAD_REQUEST, "Invalid chunked body"),
+    })? {
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+async fn decode_body(body: Vec<u8>, encoding: &str, limit: usize) -> ServerResult<Vec<u8>> {
+    let mut decoded = vec![];
+    decoder(body.as_slice(), encoding)?
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decoded)
+        .await
+        .map_err(|_| ServerError::new(StatusCode::BAD_REQUEST, "Invalid encoded body"))?;
+    if decoded.len() > limit {
+        return Err(too_large());
+    }
+    Ok(decoded)
+}
+
+// Reader decoding a body sent with the given Content-Encoding
+fn decoder<'a>(
+    body: impl futures_util::io::AsyncBufRead + Send + 'a,
+    encoding: &str,
+) -> ServerResult<Pin<Box<dyn AsyncRead + Send + 'a>>> {
+    Ok(match encoding {
+        "identity" => Box::pin(body),
+        "gzip" | "x-gzip" => Box::pin(GzipDecoder::new(body)),
+        "deflate" => Box::pin(ZlibDecoder::new(body)),
+        "br" => Box::pin(BrotliDecoder::new(body)),
+        "zstd" => Box::pin(ZstdDecoder::new(body)),
+        _ => {
+            return Err(ServerError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                &format!("Unsupported Content-Encoding: {}", encoding),
+            ))
+        }
+    })
+}
+
+fn too_large() -> ServerError {
+    ServerError::new(StatusCode::PAYLOAD_TOO_LARGE, "Request body too large")
+}
+
+#[cfg(test)]
+mod tests {
+    use async_compression::futures::bufread::GzipEncoder;
+
+    use super::*;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+    }
+
+    fn chunked(body: &str, limit: usize) -> ServerResult<Vec<u8>> {
+        runtime().block_on(read_chunked(&mut body.as_bytes(), limit))
+    }
+
+    const FORM: &[u8] = b"--form-boundary\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHoliday\r\n--form-boundary--\r\n";
+
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![];
+        runtime()
+            .block_on(GzipEncoder::new(body).read_to_end(&mut encoded))
             .unwrap();
+        encoded
+    }
+
+    // Splits the body in chunks of 10 bytes
+    fn chunks(body: &[u8]) -> Vec<u8> {
+        let mut chunked = vec![];
+        for chunk in body.chunks(10) {
+            chunked.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+            chunked.extend_from_slice(chunk);
+            chunked.extend_from_slice(b"\r\n");
+        }
+        chunked.extend_from_slice(b"0\r\n\r\n");
+        chunked
+    }
+
+    fn multipart(body: &[u8], headers: &[(&str, &str)], limits: &FormLimits) -> ServerResult<Form> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(
+                http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        let mut reader = body;
+        let boundary = "form-boundary".to_string();
+        runtime().block_on(read_multipart(&mut reader, &map, boundary, limits))
+    }
+
+    #[test]
+    fn reads_chunked_body() {
+        let body = chunked(
+            "5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\n",
+            64,
+        );
+        assert_eq!(body.unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn rejects_chunks_over_limit() {
+        let error = chunked("5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n", 8);
+        assert_eq!(error.unwrap_err().code(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn rejects_huge_chunk_size() {
+        let error = chunked("5\r\nhello\r\nffffffffffffffff\r\n", 64);
+        assert_eq!(error.unwrap_err().code(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn rejects_invalid_chunks() {
+        let error = chunked("zz\r\nhello\r\n0\r\n\r\n", 64);
+        assert_eq!(error.unwrap_err().code(), StatusCode::BAD_REQUEST);
+        let error = chunked("a\r\nhello", 64);
+        assert_eq!(error.unwrap_err().code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn streams_multipart_bodies_in_any_encoding() {
+        let limits = FormLimits::default();
+        let length = FORM.len().to_string();
+        let gzipped = gzip(FORM);
+        let gzipped_length = gzipped.len().to_string();
+        let bodies = [
+            (FORM.to_vec(), vec![("Content-Length", length.as_str())]),
+            (
+                FORM.to_vec(),
+                vec![
+                    ("Content-Length", length.as_str()),
+                    ("Content-Encoding", "identity"),
+                ],
+            ),
+            (chunks(FORM), vec![("Transfer-Encoding", "chunked")]),
+            (
+                gzipped.clone(),
+                vec![
+                    ("Content-Length", gzipped_length.as_str()),
+                    ("Content-Encoding", "gzip"),
+                ],
+            ),
+            (
+                chunks(&gzipped),
+                vec![
+                    ("Transfer-Encoding", "chunked"),
+                    ("Content-Encoding", "GZIP"),
+                ],
+            ),
+        ];
+        for (body, headers) in bodies {
+            let form = multipart(&body, &headers, &limits).unwrap();
+            assert_eq!(form.get("title"), Some("Holiday"), "{:?}", headers);
+        }
+    }
+
+    #[test]
+    fn limits_multipart_bodies_by_the_form_limits() {
+        let limits = FormLimits {
+            max_size: 32,
+            ..Default::default()
+        };
+        let length = FORM.len().to_string();
+        let error = multipart(FORM, &[("Content-Length", &length)], &limits).unwrap_err();
+        assert_eq!(error.code(), StatusCode::PAYLOAD_TOO_LARGE);
+        let chunked = [("Transfer-Encoding", "chunked")];
+        let error = multipart(&chunks(FORM), &chunked, &limits).unwrap_err();
+        assert_eq!(error.code(), StatusCode::PAYLOAD_TOO_LARGE);
+        let huge = b"ffffffffffffffff\r\n";
+        let error = multipart(huge, &chunked, &limits).unwrap_err();
+        assert_eq!(error.code(), StatusCode::PAYLOAD_TOO_LARGE);
+        // Compressed bodies are limited once decoded
+        let form = String::from_utf8_lossy(FORM).replace("Holiday", &"x".repeat(2000));
+        let gzipped = gzip(form.as_bytes());
+        let headers = [
+            ("Transfer-Encoding", "chunked"),
+            ("Content-Encoding", "gzip"),
+        ];
+        let limits = FormLimits {
+            max_size: gzipped.len() as u64 + 4,
+            ..Default::default()
+        };
+        assert!(gzipped.len() < 128);
+        let error = multipart(&chunks(&gzipped), &headers, &limits).unwrap_err();
+        assert_eq!(error.code(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
 
-        Ok(Self(request))
+    #[test]
+    fn rejects_unknown_multipart_encodings() {
+        let limits = FormLimits::default();
+        let length = FORM.len().to_string();
+        let headers = [
+            ("Content-Length", length.as_str()),
+            ("Content-Encoding", "lzma"),
+        ];
+        let error = multipart(FORM, &headers, &limits).unwrap_err();
+        assert_eq!(error.code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let error = multipart(FORM, &[], &limits).unwrap_err();
+        assert_eq!(error.code(), StatusCode::LENGTH_REQUIRED);
     }
 }
@@ -1,87 +1,373 @@
-use std::fs;
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::{self, Read, SeekFrom},
+    path::{Path, PathBuf},
+    pin::Pin,
+};
 
-use http::{Response, StatusCode};
+use futures_util::{stream, Stream};
+use http::{header::CONTENT_TYPE, HeaderValue, Response, StatusCode};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
-use super::{ServerError, ServerResult};
+use crate::common::log::{log_message, LogLevel};
 
-pub type ResponseBody = Vec<u8>;
+use super::{
+    conditional,
+    mime::{Disposition, MimeType, MimeTypes},
+    ServerError, ServerResult,
+};
+
+// Amount of bytes used to sniff the type of files with unknown extensions
+const SNIFF_LENGTH: usize = 512;
+// Size of the chunks read from disk while streaming a file
+const CHUNK_SIZE: usize = 64 * 1024;
+// Characters kept as they are in an encoded file name, the attr-char set of RFC 8187
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+pub type BodyStream = Pin<Box<dyn Stream<Item = io::Result<Vec<u8>>> + Send>>;
+
+// Response bodies are either kept in memory or produced while the response is written, files are
+// read in chunks so they never have to fit in memory
+pub enum ResponseBody {
+    Bytes(Vec<u8>),
+    File(FileBody),
+    Stream(BodyStream),
+}
+
+// A slice of a file on disk, the whole file unless a range of it was requested
+#[derive(Debug)]
+pub struct FileBody {
+    pub file: File,
+    pub path: PathBuf,
+    pub start: u64,
+    pub len: u64,
+}
 
 pub type ServerResponse = Response<Option<ResponseBody>>;
 
+impl ResponseBody {
+    // Length of the body when it is known before writing it
+    pub fn content_length(&self) -> Option<u64> {
+        match self {
+            ResponseBody::Bytes(bytes) => Some(bytes.len() as u64),
+            ResponseBody::File(file) => Some(file.len),
+            ResponseBody::Stream(_) => None,
+        }
+    }
+
+    pub fn into_stream(self) -> BodyStream {
+        match self {
+            ResponseBody::Bytes(bytes) => Box::pin(stream::once(async { Ok(bytes) })),
+            ResponseBody::File(file) => file.into_stream(),
+            ResponseBody::Stream(stream) => stream,
+        }
+    }
+}
+
+impl FileBody {
+    fn into_stream(self) -> BodyStream {
+        let file = tokio::fs::File::from_std(self.file);
+        Box::pin(stream::try_unfold(
+            (file, Some(self.start), self.len),
+            |(mut file, seek, remaining)| async move {
+                if remaining == 0 {
+                    return Ok(None);
+                }
+                if let Some(start) = seek {
+                    file.seek(SeekFrom::Start(start)).await?;
+                }
+                let mut chunk = vec![0; remaining.min(CHUNK_SIZE as u64) as usize];
+                let read = file.read(&mut chunk).await?;
+                if read == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "File truncated while streaming",
+                    ));
+                }
+                chunk.truncate(read);
+                Ok(Some((chunk, (file, None, remaining - read as u64))))
+            },
+        ))
+    }
+}
+
+impl Debug for ResponseBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResponseBody::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            ResponseBody::File(file) => write!(f, "File({:?})", file.path),
+            ResponseBody::Stream(_) => write!(f, "Stream"),
+        }
+    }
+}
+
+impl From<Vec<u8>> for ResponseBody {
+    fn from(value: Vec<u8>) -> Self {
+        ResponseBody::Bytes(value)
+    }
+}
+
+impl From<String> for ResponseBody {
+    fn from(value: String) -> Self {
+        ResponseBody::Bytes(value.into_bytes())
+    }
+}
+
+impl From<&str> for ResponseBody {
+    fn from(value: &str) -> Self {
+        ResponseBody::Bytes(value.as_bytes().to_vec())
+    }
+}
+
 trait BasicResponse {
+    // Header values can come from file names or callers, the ones that can't be sent are errors
     fn create_base(
         code: StatusCode,
         headers: Vec<(&str, &str)>,
         body: Option<ResponseBody>,
-    ) -> ServerResponse {
+    ) -> ServerResult<ServerResponse> {
         let mut response = Response::builder().status(code);
         for (k, v) in headers {
             response = response.header(k, v);
         }
-        response.body(body).expect("Error building response")
+        response
+            .body(body)
+            .map_err(|e| ServerError::err(&format!("Error building response: {}", e)).log())
+    }
+
+    fn create_typed(
+        code: StatusCode,
+        content_type: &'static str,
+        body: Option<ResponseBody>,
+    ) -> ServerResponse {
+        let mut response = Response::new(body);
+        *response.status_mut() = code;
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        response
     }
 }
 
 impl BasicResponse for ServerResponse {}
 
 pub trait IntoResponse {
-    fn create(code: StatusCode, body: ResponseBody) -> Self;
+    fn create(code: StatusCode, body: impl Into<ResponseBody>) -> Self;
     fn html(body: String) -> Self;
     fn file(filename: &str) -> ServerResult<ServerResponse>;
-    fn download(filename: &str, body: ResponseBody) -> Self;
+    fn file_with(
+        filename: &str,
+        types: &MimeTypes,
+        disposition: Option<Disposition>,
+    ) -> ServerResult<ServerResponse>;
+    fn download(filename: &str, body: impl Into<ResponseBody>) -> ServerResult<ServerResponse>;
     fn json(body: &str) -> Self;
-    fn into_bytes(self) -> Vec<u8>;
+    #[cfg(feature = "json")]
+    fn json_value<T: serde::Serialize>(value: &T) -> ServerResult<ServerResponse>;
+    fn stream(
+        content_type: &str,
+        body: impl Stream<Item = io::Result<Vec<u8>>> + Send + 'static,
+    ) -> ServerResult<ServerResponse>;
+    fn head_bytes(&self) -> Vec<u8>;
 }
 
 impl IntoResponse for ServerResponse {
-    fn create(code: StatusCode, body: ResponseBody) -> Self {
-        Self::create_base(code, vec![("Content-Type", "text/plain")], Some(body))
+    fn create(code: StatusCode, body: impl Into<ResponseBody>) -> Self {
+        Self::create_typed(code, "text/plain", Some(body.into()))
     }
     fn html(body: String) -> Self {
-        Self::create_base(
-            StatusCode::OK,
-            vec![("Content-Type", "text/html")],
-            Some(body.into()),
-        )
+        Self::create_typed(StatusCode::OK, "text/html", Some(body.into()))
     }
     fn file(filename: &str) -> ServerResult<Self> {
-        let fc = fs::read(filename)
-            .map_err(|e| ServerError::new(StatusCode::NOT_FOUND, &e.to_string()))?;
-        Ok(ServerResponse::download(filename, fc))
+        Self::file_with(filename, MimeTypes::standard(), None)
+    }
+
+    fn file_with(
+        filename: &str,
+        types: &MimeTypes,
+        disposition: Option<Disposition>,
+    ) -> ServerResult<Self> {
+        // The OS error names the path and the cause, it is only logged
+        let not_found = |e: io::Error| {
+            log_message(
+                LogLevel::Warn,
+                &format!("Error opening {}: {}", filename, e),
+            );
+            ServerError::new(StatusCode::NOT_FOUND, "File not found")
+        };
+        let mut file = File::open(filename).map_err(not_found)?;
+        let metadata = file.metadata().map_err(not_found)?;
+        let len = metadata.len();
+        let path = Path::new(filename);
+        let mime = match types.from_extension(path) {
+            Some(mime) => mime.clone(),
+            None => {
+                let mut head = Vec::with_capacity(SNIFF_LENGTH);
+                (&mut file)
+                    .take(SNIFF_LENGTH as u64)
+                    .read_to_end(&mut head)
+                    .map_err(not_found)?;
+                types.guess(path, &head)
+            }
+        };
+        let disposition = disposition.unwrap_or(mime.disposition);
+        let body = FileBody {
+            file,
+            path: path.to_path_buf(),
+            start: 0,
+            len,
+        };
+        let disposition = disposition_header(path, disposition);
+        let mut headers = vec![
+            ("Content-Type", mime.content_type.as_str()),
+            ("Content-Disposition", disposition.as_str()),
+        ];
+        let validators = metadata.modified().ok().map(|modified| {
+            (
+                conditional::file_etag(modified, len),
+                httpdate::fmt_http_date(modified),
+            )
+        });
+        if let Some((etag, last_modified)) = &validators {
+            headers.push(("ETag", etag));
+            headers.push(("Last-Modified", last_modified));
+        }
+        Self::create_base(StatusCode::OK, headers, Some(ResponseBody::File(body)))
     }
 
-    fn download(filename: &str, body: ResponseBody) -> Self {
+    fn download(filename: &str, body: impl Into<ResponseBody>) -> ServerResult<Self> {
+        let path = Path::new(filename);
+        let mime = MimeTypes::standard()
+            .from_extension(path)
+            .cloned()
+            .unwrap_or_else(MimeType::octet_stream);
         Self::create_base(
             StatusCode::OK,
-            vec![(
-                "Content-Disposition",
-                &format!("attachment; filename=\"{filename}\""),
-            )],
-            Some(body),
+            vec![
+                ("Content-Type", &mime.content_type),
+                (
+                    "Content-Disposition",
+                    &disposition_header(path, Disposition::Attachment),
+                ),
+            ],
+            Some(body.into()),
         )
     }
     fn json(body: &str) -> Self {
-        Self::create_base(
+        Self::create_typed(StatusCode::OK, "application/json", Some(body.into()))
+    }
+
+    #[cfg(feature = "json")]
+    fn json_value<T: serde::Serialize>(value: &T) -> ServerResult<Self> {
+        let body = serde_json::to_vec(value)
+            .map_err(|e| ServerError::err(&format!("Error serializing response: {}", e)).log())?;
+        Ok(Self::create_typed(
             StatusCode::OK,
-            vec![("Content-Type", "application/json")],
+            "application/json",
             Some(body.into()),
-        )
+        ))
     }
 
-    fn into_bytes(self) -> Vec<u8> {
-        let head = format!("HTTP/1.1 {}", self.status());
-        let headers = self
-            .headers()
-            .iter()
-            .map(|(k, v)| format!("{}: {}\r\n", k, v.to_str().unwrap()))
-            .collect::<String>();
-        let body = self.body().clone().unwrap_or(vec![]);
-        format!(
-            "{}\r\n{}\r\n{}",
-            head,
-            headers,
-            String::from_utf8_lossy(&body)
+    fn stream(
+        content_type: &str,
+        body: impl Stream<Item = io::Result<Vec<u8>>> + Send + 'static,
+    ) -> ServerResult<Self> {
+        Self::create_base(
+            StatusCode::OK,
+            vec![("Content-Type", content_type)],
+            Some(ResponseBody::Stream(Box::pin(body))),
         )
-        .into()
+    }
+
+    // Header values are written as raw bytes, they aren't always valid text
+    fn head_bytes(&self) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status()).into_bytes();
+        for (name, value) in self.headers() {
+            head.extend_from_slice(name.as_str().as_bytes());
+            head.extend_from_slice(b": ");
+            head.extend_from_slice(value.as_bytes());
+            head.extend_from_slice(b"\r\n");
+        }
+        head.extend_from_slice(b"\r\n");
+        head
+    }
+}
+
+// Only the file name is sent to the client, never the path it was served from. Names that aren't
+// plain ASCII get a fallback with the other characters replaced, the exact name is sent
+// percent-encoded in filename* (RFC 6266)
+fn disposition_header(path: &Path, disposition: Disposition) -> String {
+    let filename = path
+        .file_name()
+        .map(|f| f.to_string_lossy())
+        .unwrap_or_default();
+    let fallback = filename
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            ' '..='~' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    let disposition = match disposition {
+        Disposition::Inline => "inline",
+        Disposition::Attachment => "attachment",
+    };
+    match fallback == filename {
+        true => format!("{disposition}; filename=\"{fallback}\""),
+        false => format!(
+            "{disposition}; filename=\"{fallback}\"; filename*=UTF-8''{}",
+            utf8_percent_encode(&filename, ATTR_CHAR)
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_file_names_are_kept() {
+        let header = disposition_header(Path::new("/srv/files/report.pdf"), Disposition::Inline);
+        assert_eq!(header, "inline; filename=\"report.pdf\"");
+    }
+
+    #[test]
+    fn other_file_names_are_encoded() {
+        let path = Path::new("/srv/files/café \"1\".jpg");
+        let header = disposition_header(path, Disposition::Attachment);
+        assert_eq!(
+            header,
+            "attachment; filename=\"caf_ _1_.jpg\"; filename*=UTF-8''caf%C3%A9%20%221%22.jpg"
+        );
+        assert!(HeaderValue::from_str(&header).is_ok());
+    }
+
+    #[test]
+    fn serves_files_with_any_name() {
+        let path = std::env::temp_dir().join("response-test-café\r\n.txt");
+        std::fs::write(&path, "hello").unwrap();
+        let response = ServerResponse::file(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let response = response.unwrap();
+        let header = response.headers().get("Content-Disposition").unwrap();
+        assert!(header
+            .to_str()
+            .unwrap()
+            .contains("response-test-caf%C3%A9%0D%0A.txt"));
     }
 }
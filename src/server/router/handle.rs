@@ -0,0 +1,135 @@
+use std::sync::{Arc, Mutex};
+
+use arc_swap::ArcSwap;
+
+use crate::server::ServerError;
+
+use super::router::{Router, RouterBuilder};
+
+// Shared handle to the routes of a running server. Requests load the current router when they
+// start resolving and keep it until they are done, so a swap only affects the requests after it
+#[derive(Clone)]
+pub struct RouterHandle {
+    current: Arc<ArcSwap<Router>>,
+    // Updates build from the current routes, they are serialized so none of them is lost
+    update: Arc<Mutex<()>>,
+}
+
+impl RouterHandle {
+    pub fn new(router: Router) -> Self {
+        Self {
+            current: Arc::new(ArcSwap::from_pointee(router)),
+            update: Arc::default(),
+        }
+    }
+
+    pub fn load(&self) -> Arc<Router> {
+        self.current.load_full()
+    }
+
+    pub fn replace(&self, router: Router) {
+        let _update = self.update.lock().unwrap_or_else(|e| e.into_inner());
+        self.current.store(Arc::new(router));
+    }
+
+    // Changes the routes the current router was built from and swaps in the result, the current
+    // router stays in place when the changed routes don't build
+    pub fn update(&self, changes: impl FnOnce(&mut RouterBuilder)) -> Result<(), Vec<ServerError>> {
+        let _update = self.update.lock().unwrap_or_else(|e| e.into_inner());
+        let mut builder = self.current.load().builder();
+        changes(&mut builder);
+        self.current.store(Arc::new(builder.build()?));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{Request, StatusCode};
+
+    use super::{super::router::PathArguments, *};
+    use crate::server::{
+        auth::{AuthBuilder, Authentication},
+        request::ServerRequest,
+        response::{IntoResponse, ResponseBody, ServerResponse},
+        ServerResult,
+    };
+
+    fn hello(_: ServerRequest, _: PathArguments) -> ServerResult<ServerResponse> {
+        Ok(ServerResponse::create(StatusCode::OK, "hello"))
+    }
+
+    fn bye(_: ServerRequest, _: PathArguments) -> ServerResult<ServerResponse> {
+        Ok(ServerResponse::create(StatusCode::OK, "bye"))
+    }
+
+    fn get(router: &Router, path: &str) -> ServerResult<String> {
+        let uri = format!("{}?username=user&password=pass", path);
+        let request = ServerRequest::new(Request::get(uri).body(None).unwrap());
+        let auth = AuthBuilder::new()
+            .allow_user(Authentication::new("user", "pass"))
+            .build();
+        let response = router.resolve(request, &auth)?;
+        match response.into_body() {
+            Some(ResponseBody::Bytes(bytes)) => Ok(String::from_utf8(bytes).unwrap()),
+            _ => panic!("Expected an in memory body"),
+        }
+    }
+
+    fn handle() -> RouterHandle {
+        let mut builder = RouterBuilder::new();
+        builder.get("/hello", hello).get("/bye", bye);
+        RouterHandle::new(builder.build().unwrap())
+    }
+
+    #[test]
+    fn updates_swap_in_the_changed_routes() {
+        let handle = handle();
+        let before = handle.load();
+        handle
+            .update(|builder| {
+                builder.remove("/bye").get("/hello/[name]", hello);
+            })
+            .unwrap();
+        let after = handle.load();
+        assert_eq!(get(&after, "/hello/you").unwrap(), "hello");
+        assert_eq!(
+            get(&after, "/bye").unwrap_err().code(),
+            StatusCode::NOT_FOUND
+        );
+        // Requests that loaded the router before the update keep their routes
+        assert_eq!(get(&before, "/bye").unwrap(), "bye");
+        assert_eq!(
+            get(&before, "/hello/you").unwrap_err().code(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn failed_updates_keep_the_current_router() {
+        let handle = handle();
+        let errors = handle
+            .update(|builder| {
+                builder
+                    .remove("/hello")
+                    .get("/bye", hello)
+                    .get("/[broken", hello);
+            })
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        let current = handle.load();
+        assert_eq!(get(&current, "/hello").unwrap(), "hello");
+        assert_eq!(get(&current, "/bye").unwrap(), "bye");
+    }
+
+    #[test]
+    fn replaces_the_router() {
+        let handle = handle();
+        handle.replace(RouterBuilder::new().build().unwrap());
+        let current = handle.load();
+        assert_eq!(
+            get(&current, "/hello").unwrap_err().code(),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -1,5 +1,9 @@
+pub mod handle;
+#[cfg(feature = "json")]
+pub mod openapi;
 pub mod parser;
 pub mod router;
+pub mod static_dir;
 
 use router::PathArguments;
 
@@ -8,3 +12,29 @@ use super::response::ServerResponse;
 use super::ServerResult;
 
 pub type RequestHandler = fn(ServerRequest, PathArguments) -> ServerResult<ServerResponse>;
+
+// Middleware wraps the endpoint of every route in its scope, it either answers the request itself
+// or hands it to the next layer
+pub type Middleware = fn(ServerRequest, PathArguments, Next<'_>) -> ServerResult<ServerResponse>;
+
+type Endpoint<'a> = dyn Fn(ServerRequest, PathArguments) -> ServerResult<ServerResponse> + 'a;
+
+// The layers left to run before the endpoint, outer layers run first
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    layers: &'a [Middleware],
+    endpoint: &'a Endpoint<'a>,
+}
+
+impl<'a> Next<'a> {
+    fn new(layers: &'a [Middleware], endpoint: &'a Endpoint<'a>) -> Self {
+        Self { layers, endpoint }
+    }
+
+    pub fn run(self, request: ServerRequest, args: PathArguments) -> ServerResult<ServerResponse> {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer(request, args, Next::new(layers, self.endpoint)),
+            None => (self.endpoint)(request, args),
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use std::borrow::Cow;
+
+use serde_json::{json, Map, Value};
+
+use super::{
+    parser::{Constraint, RoutePath, RoutePathToken},
+    router::{RouteEndpoint, RouteInfo},
+};
+
+// Title and version of the API, written to the info object of the document
+#[derive(Debug, Clone, Default)]
+pub struct ApiInfo {
+    pub title: String,
+    pub version: String,
+    pub description: Option<String>,
+}
+
+impl ApiInfo {
+    pub fn new(title: &str, version: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            version: version.to_string(),
+            description: None,
+        }
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+}
+
+// Documentation of a route, schemas are JSON schema objects. Path parameters are taken from the
+// route itself and don't need to be listed
+#[derive(Debug, Clone, Default)]
+pub struct RouteDoc {
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub parameters: Vec<Parameter>,
+    pub request_body: Option<Value>,
+    pub responses: Vec<ResponseDoc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterLocation {
+    Query,
+    Header,
+}
+
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: String,
+    pub location: ParameterLocation,
+    pub required: bool,
+    pub schema: Value,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResponseDoc {
+    pub status: u16,
+    pub description: String,
+    pub schema: Option<Value>,
+}
+
+impl RouteDoc {
+    pub fn new(summary: &str) -> Self {
+        Self {
+            summary: Some(summary.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    pub fn query(mut self, name: &str, schema: Value, required: bool) -> Self {
+        self.parameters.push(Parameter::new(
+            name,
+            ParameterLocation::Query,
+            schema,
+            required,
+        ));
+        self
+    }
+
+    pub fn header(mut self, name: &str, schema: Value, required: bool) -> Self {
+        self.parameters.push(Parameter::new(
+            name,
+            ParameterLocation::Header,
+            schema,
+            required,
+        ));
+        self
+    }
+
+    // Schema of the JSON body the route expects
+    pub fn request(mut self, schema: Value) -> Self {
+        self.request_body = Some(schema);
+        self
+    }
+
+    pub fn response(mut self, status: u16, description: &str, schema: Option<Value>) -> Self {
+        self.responses.push(ResponseDoc {
+            status,
+            description: description.to_string(),
+            schema,
+        });
+        self
+    }
+}
+
+impl Parameter {
+    pub fn new(name: &str, location: ParameterLocation, schema: Value, required: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            location,
+            required,
+            schema,
+            description: None,
+        }
+    }
+}
+
+// OpenAPI 3 document of the handler routes, resources and static directories are left out. A route
+// with optional tokens is written once for every path it matches, since OpenAPI path parameters
+// are always required
+pub fn document(info: &ApiInfo, routes: &[RouteInfo]) -> Value {
+    let mut paths = Map::new();
+    for route in routes {
+        let method = match &route.endpoint {
+            RouteEndpoint::Handler(method) => method.as_str().to_lowercase(),
+            _ => continue,
+        };
+        // The template was parsed when the route was registered, it can't fail here
+        let path = match RoutePath::try_from(Cow::Owned(route.template.clone())) {
+            Ok(path) if route.template.len() > 1 && route.template.ends_with('/') => {
+                path.trailing_slash()
+            }
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+        let variants = path.variants();
+        for (index, tokens) in variants.iter().enumerate() {
+            let mut operation = operation(route, tokens);
+            // Operation ids must be unique, only the full path of the route gets the name
+            if let (Some(name), true) = (&route.name, index == variants.len() - 1) {
+                operation.insert("operationId".to_string(), json!(name));
+            }
+            let item = paths
+                .entry(path_template(tokens))
+                .or_insert_with(|| json!({}));
+            item[&method] = Value::Object(operation);
+        }
+    }
+
+    let mut info_object = json!({ "title": info.title, "version": info.version });
+    if let Some(description) = &info.description {
+        info_object["description"] = json!(description);
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": info_object,
+        "paths": paths,
+    })
+}
+
+fn operation(route: &RouteInfo, tokens: &[RoutePathToken]) -> Map<String, Value> {
+    let mut operation = Map::new();
+    let mut parameters = tokens.iter().filter_map(path_parameter).collect::<Vec<_>>();
+    let doc = route.doc.clone().unwrap_or_default();
+    if let Some(summary) = &doc.summary {
+        operation.insert("summary".to_string(), json!(summary));
+    }
+    if let Some(description) = &doc.description {
+        operation.insert("description".to_string(), json!(description));
+    }
+    if !doc.tags.is_empty() {
+        operation.insert("tags".to_string(), json!(doc.tags));
+    }
+    for parameter in &doc.parameters {
+        let location = match parameter.location {
+            ParameterLocation::Query => "query",
+            ParameterLocation::Header => "header",
+        };
+        let mut object = json!({
+            "name": parameter.name,
+            "in": location,
+            "required": parameter.required,
+            "schema": parameter.schema,
+        });
+        if let Some(description) = &parameter.description {
+            object["description"] = json!(description);
+        }
+        parameters.push(object);
+    }
+    if !parameters.is_empty() {
+        operation.insert("parameters".to_string(), Value::Array(parameters));
+    }
+    if let Some(schema) = &doc.request_body {
+        let body = json!({
+            "required": true,
+            "content": { "application/json": { "schema": schema } },
+        });
+        operation.insert("requestBody".to_string(), body);
+    }
+
+    let mut responses = Map::new();
+    for response in &doc.responses {
+        let mut object = json!({ "description": response.description });
+        if let Some(schema) = &response.schema {
+            object["content"] = json!({ "application/json": { "schema": schema } });
+        }
+        responses.insert(response.status.to_string(), object);
+    }
+    if responses.is_empty() {
+        responses.insert("200".to_string(), json!({ "description": "OK" }));
+    }
+    operation.insert("responses".to_string(), Value::Object(responses));
+    operation
+}
+
+// Variables are written as {name}, a catch-all becomes a single parameter holding the rest of
+// the path
+fn path_template(tokens: &[RoutePathToken]) -> String {
+    let segments = tokens
+        .iter()
+        .map(|token| match token {
+            RoutePathToken::Static(name) => name.to_string(),
+            RoutePathToken::Variable(name, _) | RoutePathToken::CatchAll(name) => {
+                format!("{{{}}}", name)
+            }
+            RoutePathToken::Optional(_) => unreachable!("Variants have no optional tokens"),
+        })
+        .collect::<Vec<_>>();
+    format!("/{}", segments.join("/"))
+}
+
+fn path_parameter(token: &RoutePathToken) -> Option<Value> {
+    let (name, schema) = match token {
+        RoutePathToken::Variable(name, constraint) => (name, schema(constraint.as_ref())),
+        RoutePathToken::CatchAll(name) => (name, json!({ "type": "string" })),
+        _ => return None,
+    };
+    Some(json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "schema": schema,
+    }))
+}
+
+// Numeric constraints map to integer schemas, any other constraint is a pattern
+fn schema(constraint: Option<&Constraint>) -> Value {
+    let constraint = match constraint {
+        Some(constraint) => constraint,
+        None => return json!({ "type": "string" }),
+    };
+    match constraint.source() {
+        "u8" | "u16" => json!({ "type": "integer", "format": "int32", "minimum": 0 }),
+        "u32" | "u64" | "usize" => json!({ "type": "integer", "format": "int64", "minimum": 0 }),
+        "i8" | "i16" | "i32" => json!({ "type": "integer", "format": "int32" }),
+        "i64" | "isize" => json!({ "type": "integer", "format": "int64" }),
+        pattern => json!({ "type": "string", "pattern": format!("^(?:{})$", pattern) }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use super::{super::router::RouterBuilder, *};
+    use crate::server::{
+        request::ServerRequest,
+        response::{IntoResponse, ServerResponse},
+        router::router::PathArguments,
+        ServerResult,
+    };
+
+    fn ok(_: ServerRequest, _: PathArguments) -> ServerResult<ServerResponse> {
+        Ok(ServerResponse::create(StatusCode::OK, ""))
+    }
+
+    fn document_of(builder: &RouterBuilder) -> Value {
+        let router = builder.build().unwrap();
+        document(&ApiInfo::new("Devices", "1.0"), router.routes())
+    }
+
+    #[test]
+    fn writes_path_parameters_from_the_route() {
+        let mut builder = RouterBuilder::new();
+        builder
+            .get("/devices/[id:u8]/[mac:[0-9a-f]+]", ok)
+            .get("/files/[...path]", ok)
+            .static_dir("/static", "/tmp/static")
+            .resource("/", "robots.txt", "/tmp/robots.txt");
+        let document = document_of(&builder);
+        assert_eq!(document["openapi"], "3.0.3");
+        assert_eq!(
+            document["info"],
+            json!({ "title": "Devices", "version": "1.0" })
+        );
+        let paths = document["paths"].as_object().unwrap();
+        assert_eq!(
+            paths.keys().collect::<Vec<_>>(),
+            ["/devices/{id}/{mac}", "/files/{path}"]
+        );
+        let parameters = &paths["/devices/{id}/{mac}"]["get"]["parameters"];
+        assert_eq!(
+            parameters[0],
+            json!({
+                "name": "id",
+                "in": "path",
+                "required": true,
+                "schema": { "type": "integer", "format": "int32", "minimum": 0 },
+            })
+        );
+        assert_eq!(
+            parameters[1]["schema"],
+            json!({ "type": "string", "pattern": "^(?:[0-9a-f]+)$" })
+        );
+        assert_eq!(
+            paths["/files/{path}"]["get"]["parameters"][0]["schema"],
+            json!({ "type": "string" })
+        );
+        assert_eq!(
+            paths["/files/{path}"]["get"]["responses"],
+            json!({ "200": { "description": "OK" } })
+        );
+    }
+
+    #[test]
+    fn writes_every_variant_of_optional_routes() {
+        let mut builder = RouterBuilder::new();
+        builder
+            .get("/devices/[id]/[tab?]", ok)
+            .name("device")
+            .post("/devices/[id]/[tab?]", ok);
+        let document = document_of(&builder);
+        let paths = &document["paths"];
+        assert_eq!(paths.as_object().unwrap().len(), 2);
+        let short = &paths["/devices/{id}"];
+        let full = &paths["/devices/{id}/{tab}"];
+        assert_eq!(short["get"]["parameters"].as_array().unwrap().len(), 1);
+        assert_eq!(full["get"]["parameters"].as_array().unwrap().len(), 2);
+        // Operation ids must be unique, only the full path carries the name
+        assert!(short["get"].get("operationId").is_none());
+        assert_eq!(full["get"]["operationId"], "device");
+        assert!(full["post"].get("operationId").is_none());
+    }
+
+    #[test]
+    fn writes_the_route_docs() {
+        let mut builder = RouterBuilder::new();
+        builder.put("/devices/[id]", ok).doc(
+            RouteDoc::new("Update a device")
+                .description("Replaces the device")
+                .tag("devices")
+                .query("force", json!({ "type": "boolean" }), false)
+                .header("If-Match", json!({ "type": "string" }), true)
+                .request(json!({ "type": "object" }))
+                .response(204, "Updated", None)
+                .response(404, "Missing", Some(json!({ "type": "string" }))),
+        );
+        let document = document_of(&builder);
+        let operation = &document["paths"]["/devices/{id}"]["put"];
+        assert_eq!(operation["summary"], "Update a device");
+        assert_eq!(operation["description"], "Replaces the device");
+        assert_eq!(operation["tags"], json!(["devices"]));
+        let parameters = operation["parameters"].as_array().unwrap();
+        assert_eq!(
+            parameters
+                .iter()
+                .map(|p| (p["name"].as_str().unwrap(), p["in"].as_str().unwrap()))
+                .collect::<Vec<_>>(),
+            [("id", "path"), ("force", "query"), ("If-Match", "header")]
+        );
+        assert_eq!(parameters[2]["required"], true);
+        assert_eq!(
+            operation["requestBody"]["content"]["application/json"]["schema"],
+            json!({ "type": "object" })
+        );
+        assert_eq!(
+            operation["responses"],
+            json!({
+                "204": { "description": "Updated" },
+                "404": {
+                    "description": "Missing",
+                    "content": { "application/json": { "schema": { "type": "string" } } },
+                },
+            })
+        );
+    }
+}
@@ -1,15 +1,110 @@
 use crate::server::{request::ServerRequest, ServerError};
-use std::collections::VecDeque;
+use http::StatusCode;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::Regex;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+};
+
+// Characters escaped when writing a path segment, everything but the unreserved ones
+pub const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
 
 // Route path is the path when registering a new endpoint, both static and variable tokens are
-// stored by their name
+// stored by their name. Catch-all tokens ([...name]) take every remaining segment and must come
+// last, optional tokens ([name?], [...name?]) may be left out of the query entirely. Optional
+// tokens nest, one is only matched when the optional tokens before it are present. Variables
+// can be constrained ([id:u32], [slug:[a-z0-9-]+]), values that don't satisfy the constraint
+// don't match the route. Tokens borrow from static templates and own their text otherwise
 #[derive(Debug, Clone)]
 pub enum RoutePathToken {
-    Static(&'static str),
-    Variable(&'static str),
+    Static(Cow<'static, str>),
+    Variable(Cow<'static, str>, Option<Constraint>),
+    CatchAll(Cow<'static, str>),
+    Optional(Box<RoutePathToken>),
 }
 
-#[derive(Debug)]
+// A constraint is either the name of a numeric type or a regex the whole segment must match,
+// constraints are compared by their source
+#[derive(Debug, Clone)]
+pub struct Constraint {
+    source: Cow<'static, str>,
+    kind: ConstraintKind,
+}
+
+type TypeCheck = fn(&str) -> bool;
+
+#[derive(Debug, Clone)]
+enum ConstraintKind {
+    Type(TypeCheck),
+    Pattern(Regex),
+}
+
+const TYPES: &[(&str, TypeCheck)] = &[
+    ("u8", |v| v.parse::<u8>().is_ok()),
+    ("u16", |v| v.parse::<u16>().is_ok()),
+    ("u32", |v| v.parse::<u32>().is_ok()),
+    ("u64", |v| v.parse::<u64>().is_ok()),
+    ("usize", |v| v.parse::<usize>().is_ok()),
+    ("i8", |v| v.parse::<i8>().is_ok()),
+    ("i16", |v| v.parse::<i16>().is_ok()),
+    ("i32", |v| v.parse::<i32>().is_ok()),
+    ("i64", |v| v.parse::<i64>().is_ok()),
+    ("isize", |v| v.parse::<isize>().is_ok()),
+];
+
+impl Constraint {
+    fn parse(source: Cow<'static, str>) -> Result<Self, ServerError> {
+        if let Some((_, check)) = TYPES.iter().find(|(name, _)| *name == source) {
+            return Ok(Constraint {
+                source,
+                kind: ConstraintKind::Type(*check),
+            });
+        }
+        match Regex::new(&format!("^(?:{})$", source)) {
+            Ok(regex) => Ok(Constraint {
+                source,
+                kind: ConstraintKind::Pattern(regex),
+            }),
+            Err(_) => Err(ServerError::err(&format!("Invalid constraint: {}", source)).log()),
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, value: &str) -> bool {
+        match &self.kind {
+            ConstraintKind::Type(check) => check(value),
+            ConstraintKind::Pattern(regex) => regex.is_match(value),
+        }
+    }
+}
+
+impl Display for RoutePathToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoutePathToken::Static(name) => write!(f, "{}", name),
+            RoutePathToken::Variable(name, None) => write!(f, "[{}]", name),
+            RoutePathToken::Variable(name, Some(constraint)) => {
+                write!(f, "[{}:{}]", name, constraint.source)
+            }
+            RoutePathToken::CatchAll(name) => write!(f, "[...{}]", name),
+            RoutePathToken::Optional(token) => {
+                let token = token.to_string();
+                write!(f, "{}?]", &token[..token.len() - 1])
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct RoutePath {
     tokens: VecDeque<RoutePathToken>,
 }
@@ -21,26 +116,206 @@ impl Iterator for RoutePath {
     }
 }
 
-impl TryFrom<&'static str> for RoutePath {
+impl RoutePath {
+    // Every concrete path described by the route, from the one without optional tokens to the one
+    // with all of them. Since optional tokens nest, each variant has one more than the previous
+    // one, /a/[x?]/[y?] is /a, /a/{x} and /a/{x}/{y} but never /a/{y}
+    pub fn variants(&self) -> Vec<Vec<RoutePathToken>> {
+        let optionals = self
+            .tokens
+            .iter()
+            .filter(|token| matches!(token, RoutePathToken::Optional(_)))
+            .count();
+        (0..=optionals)
+            .map(|present| {
+                let mut seen = 0;
+                self.tokens
+                    .iter()
+                    .filter_map(|token| match token {
+                        RoutePathToken::Optional(inner) => {
+                            seen += 1;
+                            (seen <= present).then(|| *inner.clone())
+                        }
+                        token => Some(token.clone()),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl RoutePath {
+    // Appends the tokens of a nested route, nothing can follow a catch-all
+    pub fn join(mut self, other: RoutePath) -> Result<Self, ServerError> {
+        let catch_all = |token: &RoutePathToken| match token {
+            RoutePathToken::CatchAll(_) => true,
+            RoutePathToken::Optional(token) => matches!(**token, RoutePathToken::CatchAll(_)),
+            _ => false,
+        };
+        if self.tokens.back().is_some_and(catch_all) && !other.tokens.is_empty() {
+            return Err(ServerError::err("Catch-all must be the last token").log());
+        }
+        self.tokens.extend(other.tokens);
+        Ok(self)
+    }
+}
+
+impl RoutePath {
+    // Names of the variables and catch-alls of the route
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.tokens.iter().filter_map(|token| match token {
+            RoutePathToken::Optional(token) => match &**token {
+                RoutePathToken::Variable(name, _) | RoutePathToken::CatchAll(name) => Some(&**name),
+                _ => None,
+            },
+            RoutePathToken::Variable(name, _) | RoutePathToken::CatchAll(name) => Some(&**name),
+            RoutePathToken::Static(_) => None,
+        })
+    }
+
+    // Writes the path with its variables filled in and percent-encoded, a catch-all takes a whole
+    // path and keeps its slashes. Optional tokens are left out when they have no argument, an
+    // optional token can't be given once one before it was left out. Values
+    // the router would reject when decoding the path are invalid
+    pub fn url(&self, args: &HashMap<String, String>) -> Result<String, ServerError> {
+        if let Some(name) = args.keys().find(|name| !self.names().any(|n| n == *name)) {
+            return Err(ServerError::err(&format!("Unknown argument: {}", name)));
+        }
+        let invalid = |name: &str| ServerError::err(&format!("Invalid argument: {}", name));
+        let mut url = String::new();
+        let mut omitted = None;
+        for token in &self.tokens {
+            let (token, optional) = match token {
+                RoutePathToken::Optional(token) => (&**token, true),
+                token => (token, false),
+            };
+            let segment = match token {
+                RoutePathToken::Static(name) => Some(name.to_string()),
+                RoutePathToken::Variable(name, constraint) => match args.get(&**name) {
+                    Some(value)
+                        if !is_segment_value(value)
+                            || constraint.as_ref().is_some_and(|c| !c.matches(value)) =>
+                    {
+                        return Err(invalid(name))
+                    }
+                    Some(value) => Some(utf8_percent_encode(value, SEGMENT).to_string()),
+                    None => None,
+                },
+                RoutePathToken::CatchAll(name) => match args.get(&**name) {
+                    Some(value) => {
+                        let segments = value
+                            .split('/')
+                            .filter(|segment| !segment.is_empty())
+                            .collect::<Vec<_>>();
+                        if segments.is_empty() || !segments.iter().all(|s| is_segment_value(s)) {
+                            return Err(invalid(name));
+                        }
+                        let segments = segments
+                            .iter()
+                            .map(|segment| utf8_percent_encode(segment, SEGMENT).to_string())
+                            .collect::<Vec<_>>();
+                        Some(segments.join("/"))
+                    }
+                    None => None,
+                },
+                RoutePathToken::Optional(_) => unreachable!("Optional tokens are not nested"),
+            };
+            if let (Some(_), true, Some(name)) = (&segment, optional, omitted) {
+                return Err(ServerError::err(&format!("Missing argument: {}", name)));
+            }
+            match (segment, token) {
+                (Some(segment), _) => {
+                    url.push('/');
+                    url.push_str(&segment);
+                }
+                (None, RoutePathToken::Variable(name, _) | RoutePathToken::CatchAll(name))
+                    if optional =>
+                {
+                    omitted = omitted.or(Some(&**name));
+                }
+                (None, RoutePathToken::Variable(name, _) | RoutePathToken::CatchAll(name)) => {
+                    return Err(ServerError::err(&format!("Missing argument: {}", name)))
+                }
+                (None, _) => unreachable!("Static tokens always have a segment"),
+            }
+        }
+        if url.is_empty() {
+            url.push('/');
+        }
+        Ok(url)
+    }
+
+    // Matches the route only when the path ends with a slash, the slash is an empty last token.
+    // A catch-all already takes the rest of the path
+    pub fn trailing_slash(mut self) -> Self {
+        match self.tokens.back() {
+            Some(RoutePathToken::CatchAll(_)) => {}
+            Some(RoutePathToken::Optional(token))
+                if matches!(**token, RoutePathToken::CatchAll(_)) => {}
+            _ if self.tokens.is_empty() => {}
+            _ => self
+                .tokens
+                .push_back(RoutePathToken::Static(Cow::Borrowed(""))),
+        }
+        self
+    }
+}
+
+impl TryFrom<Cow<'static, str>> for RoutePath {
     type Error = ServerError;
-    fn try_from(value: &'static str) -> Result<Self, Self::Error> {
+    fn try_from(value: Cow<'static, str>) -> Result<Self, Self::Error> {
+        match value {
+            Cow::Borrowed(value) => Self::parse(value, Cow::Borrowed),
+            Cow::Owned(value) => Self::parse(&value, |token| Cow::Owned(token.to_string())),
+        }
+    }
+}
+
+impl RoutePath {
+    fn parse<'a>(
+        value: &'a str,
+        text: impl Fn(&'a str) -> Cow<'static, str>,
+    ) -> Result<Self, ServerError> {
         let mut tokens = VecDeque::new();
 
         let str = value.trim_matches('/');
-        let mut split = str.split("/");
-        loop {
-            let token = match split.next() {
-                Some(t) => t,
-                None => break,
-            };
+        let mut split = str.split("/").filter(|t| !t.is_empty()).peekable();
+        while let Some(token) = split.next() {
+            // Only the outer brackets are stripped, regex constraints may contain their own. A
+            // trailing ? always marks the token as optional
             if token.starts_with('[') && token.ends_with(']') {
-                let token = token.trim_matches(|c| c == '[' || c == ']');
-                if !token.chars().all(char::is_alphanumeric) {
+                let token = &token[1..token.len() - 1];
+                let (token, optional) = match token.strip_suffix('?') {
+                    Some(token) => (token, true),
+                    None => (token, false),
+                };
+                let (token, catch_all) = match token.strip_prefix("...") {
+                    Some(token) => (token, true),
+                    None => (token, false),
+                };
+                let (name, constraint) = match token.split_once(':') {
+                    Some((name, constraint)) => (name, Some(Constraint::parse(text(constraint))?)),
+                    None => (token, None),
+                };
+                if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
                     return Err(ServerError::err("Invalid token").log());
                 }
-                tokens.push_back(RoutePathToken::Variable(token));
-            } else if token.chars().all(char::is_alphanumeric) {
-                tokens.push_back(RoutePathToken::Static(token));
+                if catch_all && split.peek().is_some() {
+                    return Err(ServerError::err("Catch-all must be the last token").log());
+                }
+                let token = match (catch_all, constraint) {
+                    (true, None) => RoutePathToken::CatchAll(text(name)),
+                    (true, Some(_)) => {
+                        return Err(ServerError::err("Catch-all can't be constrained").log())
+                    }
+                    (false, constraint) => RoutePathToken::Variable(text(name), constraint),
+                };
+                tokens.push_back(match optional {
+                    true => RoutePathToken::Optional(Box::new(token)),
+                    false => token,
+                });
+            } else if token.chars().all(is_unreserved) && !is_dot_segment(token) {
+                tokens.push_back(RoutePathToken::Static(text(token)));
             } else {
                 return Err(ServerError::err("Error reading token").log());
             }
@@ -50,44 +325,96 @@ impl TryFrom<&'static str> for RoutePath {
 }
 
 // Query path is the path when querying the system, variable token names' are unknown so the value
-// of the token is actually the value of the variable
+// of the token is actually the value of the variable. Tokens are stored decoded, dotted segments
+// are allowed anywhere but only the last one is treated as a resource
 #[derive(Debug)]
 pub struct QueryPath {
     pub tokens: VecDeque<String>,
     pub resource: Option<String>,
+    pub trailing_slash: bool,
 }
 
-impl TryFrom<ServerRequest> for QueryPath {
+impl QueryPath {
+    // Every segment of the path, including the resource
+    pub fn segments(&self) -> Vec<String> {
+        self.tokens.iter().chain(&self.resource).cloned().collect()
+    }
+}
+
+// Segments are percent-decoded after the dot segments are resolved, a decoded segment can't
+// introduce a separator or a dot segment of its own and the path can never climb above the root
+impl TryFrom<&ServerRequest> for QueryPath {
     type Error = ServerError;
-    fn try_from(value: ServerRequest) -> Result<Self, Self::Error> {
-        let mut tokens = VecDeque::new();
-        let mut resource = None;
+    fn try_from(value: &ServerRequest) -> Result<Self, Self::Error> {
+        let mut segments: Vec<String> = vec![];
+        for segment in value.path().split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    if segments.pop().is_none() {
+                        return Err(bad_request("Path escapes the root"));
+                    }
+                }
+                segment => segments.push(decode_segment(segment)?),
+            }
+        }
+
+        // Only the last segment can be a resource, dotted segments before it are plain tokens
+        let resource = match segments.last() {
+            Some(last) if last.contains('.') => segments.pop(),
+            _ => None,
+        };
+        // The root has no trailing slash, only paths below it
         let path = value.path();
-        let path_tokens = path.trim_matches('/').split('/');
-        let mut size = path_tokens.clone().count();
+        let trailing_slash = (path.ends_with('/') || path.ends_with("/.") || path.ends_with("/.."))
+            && !(segments.is_empty() && resource.is_none());
+        Ok(QueryPath {
+            trailing_slash,
+            tokens: segments.into(),
+            resource,
+        })
+    }
+}
 
-        for token in path_tokens {
-            if token.contains('.') {
-                if !token.chars().all(|c| c.is_alphanumeric() || c == '.') {
-                    return Err(ServerError::err("Invalid token").log());
-                }
-                if size == 1 {
-                    resource = Some(token.to_string());
-                } else {
-                    return Err(ServerError::err("Invalid query").log());
-                }
-            } else {
-                if !token.chars().all(char::is_alphanumeric) {
-                    return Err(
-                        ServerError::err(format!("Invalid token: {}", token).as_str()).log(),
-                    );
-                }
-                tokens.push_back(token.to_string());
+fn decode_segment(segment: &str) -> Result<String, ServerError> {
+    let mut chars = segment.chars();
+    while let Some(c) = chars.next() {
+        let valid = match c {
+            '%' => {
+                chars.next().is_some_and(|c| c.is_ascii_hexdigit())
+                    && chars.next().is_some_and(|c| c.is_ascii_hexdigit())
             }
-            size -= 1;
+            c => is_unreserved(c) || "!$&'()*+,;=:@".contains(c),
+        };
+        if !valid {
+            return Err(bad_request(&format!("Invalid path segment: {}", segment)));
         }
-        Ok(QueryPath { tokens, resource })
     }
+    let decoded = percent_decode_str(segment)
+        .decode_utf8()
+        .map_err(|_| bad_request(&format!("Invalid path segment: {}", segment)))?;
+    if !is_segment_value(&decoded) {
+        return Err(bad_request(&format!("Invalid path segment: {}", segment)));
+    }
+    Ok(decoded.into_owned())
+}
+
+// RFC 3986 unreserved characters, the only ones allowed in static route tokens
+fn is_unreserved(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "-._~".contains(c)
+}
+
+fn is_dot_segment(segment: &str) -> bool {
+    segment == "." || segment == ".."
+}
+
+// Whether a decoded segment can be sent in a path
+fn is_segment_value(segment: &str) -> bool {
+    !segment.is_empty() && !segment.contains(['/', '\\', '\0']) && !is_dot_segment(segment)
+}
+
+fn bad_request(message: &str) -> ServerError {
+    ServerError::new(StatusCode::BAD_REQUEST, message).log()
 }
 
 impl Iterator for QueryPath {
@@ -96,3 +423,106 @@ impl Iterator for QueryPath {
         self.tokens.pop_front()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(template: &'static str) -> RoutePath {
+        RoutePath::try_from(Cow::Borrowed(template)).unwrap()
+    }
+
+    fn variants(template: &'static str) -> Vec<String> {
+        let path = route(template);
+        let variants = path.variants();
+        variants
+            .iter()
+            .map(|tokens| tokens.iter().map(|t| format!("/{}", t)).collect())
+            .collect()
+    }
+
+    fn args(args: &[(&str, &str)]) -> HashMap<String, String> {
+        args.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn optional_tokens_nest() {
+        assert_eq!(variants("/a/[x?]"), ["/a", "/a/[x]"]);
+        assert_eq!(variants("/a/[x?]/[y?]"), ["/a", "/a/[x]", "/a/[x]/[y]"]);
+        assert_eq!(
+            variants("/a/[x?]/b/[...rest?]"),
+            ["/a/b", "/a/[x]/b", "/a/[x]/b/[...rest]"]
+        );
+    }
+
+    #[test]
+    fn urls_follow_nested_optionals() {
+        let path = route("/a/[x?]/[y?]");
+        assert_eq!(path.url(&args(&[])).unwrap(), "/a");
+        assert_eq!(path.url(&args(&[("x", "1")])).unwrap(), "/a/1");
+        assert_eq!(
+            path.url(&args(&[("x", "1"), ("y", "2")])).unwrap(),
+            "/a/1/2"
+        );
+        assert!(path.url(&args(&[("y", "2")])).is_err());
+    }
+
+    fn query(path: &str) -> Result<QueryPath, StatusCode> {
+        let request = http::Request::get(path).body(None).unwrap();
+        QueryPath::try_from(&ServerRequest::new(request)).map_err(|e| e.code())
+    }
+
+    fn segments(path: &str) -> Vec<String> {
+        query(path).unwrap().segments()
+    }
+
+    #[test]
+    fn normalizes_dot_segments() {
+        assert_eq!(segments("/a/./b/../c"), ["a", "c"]);
+        assert_eq!(segments("//a///b/"), ["a", "b"]);
+        assert_eq!(segments("/a/.."), Vec::<String>::new());
+        assert_eq!(query("/..").err(), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(query("/a/../../etc").err(), Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn decodes_segments() {
+        assert_eq!(segments("/a%20b/caf%C3%A9"), ["a b", "café"]);
+        assert_eq!(segments("/it's/a=b"), ["it's", "a=b"]);
+    }
+
+    #[test]
+    fn rejects_invalid_segments() {
+        for path in [
+            "/a/%2F",
+            "/a/%2e%2e",
+            "/a/%00",
+            "/a/%5C",
+            "/a/%zz",
+            "/a/%C3",
+            "/a/%4",
+        ] {
+            assert_eq!(query(path).err(), Some(StatusCode::BAD_REQUEST), "{}", path);
+        }
+    }
+
+    #[test]
+    fn splits_the_resource() {
+        let path = query("/files/v1.2/report.pdf").unwrap();
+        assert_eq!(path.tokens, ["files", "v1.2"]);
+        assert_eq!(path.resource.as_deref(), Some("report.pdf"));
+    }
+
+    #[test]
+    fn detects_trailing_slashes() {
+        let trailing = |path| query(path).unwrap().trailing_slash;
+        assert!(trailing("/a/"));
+        assert!(trailing("/a/b/."));
+        assert!(trailing("/a/b/c/.."));
+        assert!(!trailing("/a"));
+        assert!(!trailing("/"));
+        assert!(!trailing("/a/.."));
+    }
+}
@@ -1,212 +1,1055 @@
 use std::{
+    borrow::Cow,
     collections::HashMap,
-    fmt::Debug,
-    sync::{Arc, Mutex},
+    fmt::{Debug, Display},
+    ops::Deref,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
 };
 
-use http::Method;
+use http::{
+    header::{ALLOW, LOCATION},
+    HeaderName, HeaderValue, Method, StatusCode,
+};
+use percent_encoding::utf8_percent_encode;
 
 use crate::server::{
+    auth::{AuthManager, AuthPolicy},
+    cors::{self, Cors},
+    mime::{Disposition, MimeTypes},
+    query,
     request::ServerRequest,
     response::{IntoResponse, ServerResponse},
     router::parser::RoutePath,
     ServerError, ServerResult,
 };
 
+#[cfg(feature = "json")]
+use super::openapi::{self, ApiInfo, RouteDoc};
 use super::{
-    parser::{QueryPath, RoutePathToken},
-    RequestHandler,
+    parser::{Constraint, QueryPath, RoutePathToken, SEGMENT},
+    static_dir::StaticDir,
+    Middleware, Next, RequestHandler,
 };
 
-// ServerRouter is responsible for managing and resolving paths, both when registering and handling
+// ServerRouter is responsible for managing and resolving paths, both when registering and handling.
+// The tree is compiled once when the router is built and never changes afterwards, so workers
+// resolve requests concurrently without locking it
 #[derive(Default, Debug)]
 pub struct Router {
     routes: RouteTree,
+    table: Arc<RouteTable>,
+    cors: Option<Cors>,
+    source: RouterBuilder,
+}
+
+// Summary of the routes of a router, shared with the requests it resolves so handlers can build
+// links to named routes and inspect the router
+#[derive(Debug, Default)]
+pub struct RouteTable {
+    names: HashMap<String, NamedRoute>,
+    routes: Vec<RouteInfo>,
+    tree: String,
+    #[cfg(feature = "json")]
+    openapi: Option<serde_json::Value>,
+}
+
+#[derive(Debug)]
+struct NamedRoute {
+    template: String,
+    path: RoutePath,
+}
+
+// A registered route as it was declared, with the scope it ended up in
+#[derive(Debug, Clone)]
+pub struct RouteInfo {
+    pub template: String,
+    pub endpoint: RouteEndpoint,
+    pub name: Option<String>,
+    pub auth: AuthPolicy,
+    pub layers: usize,
+    #[cfg(feature = "json")]
+    pub doc: Option<RouteDoc>,
+}
+
+#[derive(Debug, Clone)]
+pub enum RouteEndpoint {
+    Handler(Method),
+    Resource {
+        name: String,
+        location: String,
+        disposition: Option<Disposition>,
+    },
+    Directory {
+        root: PathBuf,
+        listing: bool,
+    },
+    Fallback,
+}
+
+impl Display for RouteInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.endpoint {
+            RouteEndpoint::Handler(method) => {
+                write!(f, "{:<7} {}", method.as_str(), self.template)?
+            }
+            RouteEndpoint::Resource { name, location, .. } => write!(
+                f,
+                "{:<7} {}/{} -> {}",
+                "FILE",
+                self.template.trim_end_matches('/'),
+                name,
+                location
+            )?,
+            RouteEndpoint::Directory { root, .. } => {
+                write!(f, "{:<7} {} -> {}", "DIR", self.template, root.display())?
+            }
+            RouteEndpoint::Fallback => write!(f, "{:<7} {}", "ANY", self.template)?,
+        }
+        if let Some(name) = &self.name {
+            write!(f, " name={}", name)?;
+        }
+        write!(f, " auth={} layers={}", self.auth, self.layers)
+    }
+}
+
+impl RouteTable {
+    // Every route in the order it was registered
+    pub fn routes(&self) -> &[RouteInfo] {
+        &self.routes
+    }
+
+    // The compiled tree, one node per line with the endpoints registered on it
+    pub fn tree(&self) -> &str {
+        &self.tree
+    }
+
+    // The OpenAPI document served by the openapi endpoint, if the router has one
+    #[cfg(feature = "json")]
+    pub fn openapi(&self) -> Option<&serde_json::Value> {
+        self.openapi.as_ref()
+    }
+
+    // Link to the named route, the arguments fill in its variables. Unknown names, missing or
+    // unknown arguments and values rejected by a constraint are errors
+    pub fn url_for<K: AsRef<str>, V: ToString>(
+        &self,
+        name: &str,
+        args: impl IntoIterator<Item = (K, V)>,
+    ) -> ServerResult<String> {
+        let route = self
+            .names
+            .get(name)
+            .ok_or_else(|| ServerError::err(&format!("Unknown route name: {}", name)))?;
+        let args = args
+            .into_iter()
+            .map(|(key, value)| (key.as_ref().to_string(), value.to_string()))
+            .collect();
+        route.path.url(&args).map_err(|e| {
+            ServerError::err(&format!(
+                "Invalid link to {} ({}): {}",
+                name, route.template, e.error
+            ))
+        })
+    }
 }
 
 // Route tree holds the data for the path tree
 #[derive(Default, Clone, Debug)]
 struct RouteTree {
-    root: RouteNodePointer,
+    root: RouteNode,
+    trailing_slash: PathPolicy,
+    case: PathPolicy,
+}
+
+// How a request that differs from a route only by a trailing slash or by case is handled. Strict
+// treats them as different paths, redirect answers with a permanent redirect to the registered
+// path and lenient serves the route as if the request matched it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathPolicy {
+    #[default]
+    Strict,
+    Redirect,
+    Lenient,
+}
+
+// How a query path is matched against the tree
+#[derive(Debug, Clone, Copy)]
+struct Lookup {
+    trailing_slash: bool,
+    fold_case: bool,
+}
+
+// A node accepted by a search, with the arguments and the registered path it matched
+struct Found<'a> {
+    node: &'a RouteNode,
+    args: PathArguments,
+    path: Vec<String>,
 }
 
 // A route node is a single node in the tree, it corresponds to a token in the path (ex:
-// /api/test/ -> both "api" and "test" have a corresponding node
+// /api/test/ -> both "api" and "test" have a corresponding node. Nodes own their children
 #[derive(Default, Clone, Debug)]
 pub struct RouteNode {
-    rest: [Option<RequestHandler>; 4],
-    children: Option<RouteChildren>,
-    resources: HashMap<ResourceName<'static>, ResourceLocation<'static>>,
+    route: String,
+    rest: [Option<Scoped<RequestHandler>>; 4],
+    children: RouteChildren,
+    resources: HashMap<ResourceName, Scoped<FileResource>>,
+    directory: Option<Scoped<StaticDir>>,
+    fallback: Option<Scoped<RequestHandler>>,
+}
+
+// Middleware, auth policy and mime types of the group a route was registered in
+#[derive(Debug, Clone, Default)]
+struct Scope {
+    layers: Vec<Middleware>,
+    auth: AuthPolicy,
+    mime: Arc<MimeTypes>,
+}
+
+#[derive(Debug, Clone)]
+struct Scoped<T> {
+    value: T,
+    scope: Arc<Scope>,
+}
+
+// Routes can have multiple static children, variable children (/api/[userId]/...) and a single
+// catch-all child, they are tried in that order when resolving. There is one variable child per
+// constraint, constrained ones are kept ahead of the unconstrained one
+#[derive(Default, Debug, Clone)]
+struct RouteChildren {
+    statics: HashMap<Cow<'static, str>, RouteNode>,
+    // Lowercase static tokens, to match them regardless of case
+    folded: HashMap<String, Cow<'static, str>>,
+    variables: Vec<VariableChild>,
+    catch_all: Option<(Cow<'static, str>, Box<RouteNode>)>,
 }
-pub type RouteNodePointer = Arc<Mutex<RouteNode>>;
 
-// Routes can have a single variable children (/api/[userId]/...) or multiple static children
 #[derive(Debug, Clone)]
-enum RouteChildren {
-    Static(HashMap<&'static str, RouteNodePointer>),
-    Variable(&'static str, RouteNodePointer),
+struct VariableChild {
+    name: Cow<'static, str>,
+    constraint: Option<Constraint>,
+    node: RouteNode,
+}
+
+impl VariableChild {
+    fn accepts(&self, value: &str) -> bool {
+        self.constraint.as_ref().is_none_or(|c| c.matches(value))
+    }
+
+    fn same_constraint(&self, constraint: &Option<Constraint>) -> bool {
+        self.constraint.as_ref().map(Constraint::source)
+            == constraint.as_ref().map(Constraint::source)
+    }
 }
 
-pub type ResourceName<'a> = &'a str;
-pub type ResourceLocation<'a> = &'a str;
+// Route text is borrowed when it comes from a literal and owned when it is built at runtime
+pub type RouteText = Cow<'static, str>;
+pub type ResourceName = RouteText;
+pub type ResourceLocation = RouteText;
+
+// A registered resource, the disposition overrides the one from the mime table when set
+#[derive(Debug, Clone)]
+struct FileResource {
+    location: ResourceLocation,
+    disposition: Option<Disposition>,
+}
 
+#[derive(Debug, Clone)]
 enum NodeEndpoint {
     REST(Method, RequestHandler),
-    Resource(ResourceName<'static>, ResourceLocation<'static>),
+    Resource(ResourceName, FileResource),
+    Directory(StaticDir),
+    Fallback(RequestHandler),
+}
+
+// What a query resolved to
+enum RouteMatch<'a> {
+    Resource(&'a Scoped<FileResource>),
+    Handler(&'a Scoped<RequestHandler>, PathArguments),
+    Directory(&'a Scoped<StaticDir>, Vec<String>),
+}
+
+// What a request resolves to, requests the router answers itself carry their response
+enum Resolved<'a> {
+    Route(RouteMatch<'a>),
+    Answer(ServerResponse),
+}
+
+impl RouteMatch<'_> {
+    fn scope(&self) -> &Scope {
+        match self {
+            RouteMatch::Resource(resource) => &resource.scope,
+            RouteMatch::Handler(handler, _) => &handler.scope,
+            RouteMatch::Directory(dir, _) => &dir.scope,
+        }
+    }
 }
 
 pub type PathToken = String;
 pub type TokenValue = String;
 
-pub type PathArguments = HashMap<PathToken, TokenValue>;
+// Values of the variables matched by a route, dereferences to the underlying map
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct PathArguments(HashMap<PathToken, TokenValue>);
+
+impl PathArguments {
+    // Same as the query arguments, missing or malformed values are a 400 for the client
+    pub fn parse<T: FromStr>(&self, name: &str) -> ServerResult<T> {
+        self.parse_optional(name)?
+            .ok_or_else(|| query::missing_argument("path", name))
+    }
+
+    // Same as parse, for arguments of optional tokens
+    pub fn parse_optional<T: FromStr>(&self, name: &str) -> ServerResult<Option<T>> {
+        self.0
+            .get(name)
+            .map(|value| query::parse_argument("path", name, value))
+            .transpose()
+    }
+
+    fn insert(&mut self, name: &str, value: String) {
+        self.0.insert(name.to_string(), value);
+    }
+}
+
+impl Deref for PathArguments {
+    type Target = HashMap<PathToken, TokenValue>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl IntoIterator for PathArguments {
+    type Item = (PathToken, TokenValue);
+    type IntoIter = std::collections::hash_map::IntoIter<PathToken, TokenValue>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
 
 impl Router {
-    pub fn resolve(&self, request: ServerRequest) -> ServerResult<ServerResponse> {
-        let path: QueryPath = request.clone().try_into()?;
-        let (node_p, args) = self.routes.get(&path)?;
-        let node = node_p.lock().unwrap();
-        if let Some(resource) = path.resource {
-            let real_path = node
-                .get_resource(&resource)
-                .ok_or(ServerError::err("Resource not found"))?;
-            Ok(ServerResponse::file(real_path)?)
-        } else {
-            let handler = node
-                .get_rest(request.method().clone())
-                .ok_or(ServerError::err("Method not found"))?;
-            handler(request, args)
+    // Cross-origin requests get the CORS headers on their response whether they succeed or not, so
+    // the browser lets the client read errors too
+    pub fn resolve(
+        &self,
+        request: ServerRequest,
+        auth: &AuthManager,
+    ) -> ServerResult<ServerResponse> {
+        let headers = self.cors_headers(&request);
+        match self.route(request, auth) {
+            Ok(mut response) => {
+                for (name, value) in headers {
+                    response.headers_mut().append(name, value);
+                }
+                Ok(response)
+            }
+            Err(e) => Err(headers
+                .into_iter()
+                .fold(e, |e, (name, value)| e.with_header(name, value))),
+        }
+    }
+
+    // Checks a request against the auth policy of the route it resolves to without running it,
+    // so the body of a request is only read once the request is allowed
+    pub fn authorize(&self, request: &ServerRequest, auth: &AuthManager) -> ServerResult<()> {
+        match self.matched(request, auth) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(self
+                .cors_headers(request)
+                .into_iter()
+                .fold(e, |e, (name, value)| e.with_header(name, value))),
+        }
+    }
+
+    fn cors_headers(&self, request: &ServerRequest) -> Vec<(HeaderName, HeaderValue)> {
+        match &self.cors {
+            Some(cors) if !cors::is_preflight(request) => cors.response_headers(request),
+            _ => vec![],
+        }
+    }
+
+    fn route(&self, request: ServerRequest, auth: &AuthManager) -> ServerResult<ServerResponse> {
+        let mut request = request;
+        request.set_route_table(self.table.clone());
+        let found = match self.matched(&request, auth)? {
+            Resolved::Route(found) => found,
+            Resolved::Answer(response) => return Ok(response),
+        };
+        let scope = found.scope();
+
+        let args = match &found {
+            RouteMatch::Handler(_, args) => args.clone(),
+            _ => PathArguments::default(),
+        };
+        let endpoint = |request: ServerRequest, args: PathArguments| match &found {
+            RouteMatch::Resource(resource) => {
+                let resource = &resource.value;
+                ServerResponse::file_with(&resource.location, &scope.mime, resource.disposition)
+            }
+            RouteMatch::Handler(handler, _) => (handler.value)(request, args),
+            RouteMatch::Directory(dir, rest) => dir.value.serve(rest, &request, &scope.mime),
+        };
+        Next::new(&scope.layers, &endpoint).run(request, args)
+    }
+
+    // The request is checked against the auth policy of the route it resolved to before any of
+    // its middleware runs, requests that don't resolve still need to be authenticated. OPTIONS
+    // requests are answered for every route, preflights don't carry credentials and are answered
+    // without them
+    fn matched(&self, request: &ServerRequest, auth: &AuthManager) -> ServerResult<Resolved<'_>> {
+        let path = QueryPath::try_from(request)?;
+        let lookup = Lookup {
+            trailing_slash: path.trailing_slash
+                && self.routes.trailing_slash != PathPolicy::Lenient,
+            fold_case: self.routes.case == PathPolicy::Lenient,
+        };
+        if request.method() == Method::OPTIONS {
+            return match (self.methods(&path, lookup), &self.cors) {
+                (Some(methods), Some(cors)) if cors::is_preflight(request) => {
+                    cors.preflight(request, &methods).map(Resolved::Answer)
+                }
+                (Some(mut methods), _) => {
+                    auth.check(request, &AuthPolicy::Authenticated)?;
+                    methods.push(Method::OPTIONS.as_str());
+                    let allow = HeaderValue::from_str(&methods.join(", ")).unwrap();
+                    let mut response = ServerResponse::create(StatusCode::NO_CONTENT, "");
+                    response.headers_mut().insert(ALLOW, allow);
+                    Ok(Resolved::Answer(response))
+                }
+                // Preflights never carry credentials, they can't be asked for them
+                (None, Some(_)) if cors::is_preflight(request) => Err(not_found("Route not found")),
+                (None, _) => {
+                    auth.check(request, &AuthPolicy::Authenticated)?;
+                    Err(not_found("Route not found"))
+                }
+            };
+        }
+        let found = self
+            .find(request, &path, lookup)
+            .map(|(found, _)| found)
+            .or_else(
+                |e| match self.routes.get_directory(&path, lookup.fold_case) {
+                    Some(_) if request.method() != Method::GET => match e.code {
+                        StatusCode::METHOD_NOT_ALLOWED => Err(e),
+                        _ => Err(method_not_allowed(&[Method::GET.as_str()])),
+                    },
+                    Some((dir, rest)) => Ok(RouteMatch::Directory(dir, rest)),
+                    None => Err(e),
+                },
+            );
+        let found = match found {
+            Ok(found) => found,
+            Err(e) if e.code == StatusCode::NOT_FOUND => {
+                let fallback = self.routes.get_fallback(&path, lookup.fold_case);
+                match (self.canonical(request, &path), fallback) {
+                    (Some((found, location)), _) => {
+                        auth.check(request, &found.scope().auth)?;
+                        return redirect(&location, request.query().raw()).map(Resolved::Answer);
+                    }
+                    (None, Some(fallback)) => {
+                        RouteMatch::Handler(fallback, PathArguments::default())
+                    }
+                    (None, None) => {
+                        auth.check(request, &AuthPolicy::Authenticated)?;
+                        return Err(e);
+                    }
+                }
+            }
+            Err(e) => {
+                auth.check(request, &AuthPolicy::Authenticated)?;
+                return Err(e);
+            }
+        };
+        auth.check(request, &found.scope().auth)?;
+        Ok(Resolved::Route(found))
+    }
+
+    pub fn url_for<K: AsRef<str>, V: ToString>(
+        &self,
+        name: &str,
+        args: impl IntoIterator<Item = (K, V)>,
+    ) -> ServerResult<String> {
+        self.table.url_for(name, args)
+    }
+
+    pub fn routes(&self) -> &[RouteInfo] {
+        self.table.routes()
+    }
+
+    pub fn tree(&self) -> &str {
+        self.table.tree()
+    }
+
+    #[cfg(feature = "json")]
+    pub fn openapi(&self, info: &ApiInfo) -> serde_json::Value {
+        openapi::document(info, self.table.routes())
+    }
+
+    // The routes this router was built from, to build a changed copy of it
+    pub fn builder(&self) -> RouterBuilder {
+        self.source.clone()
+    }
+
+    // Resources are looked up first, dotted segments that aren't a registered resource can still be
+    // matched by variables or catch-alls of a handler route. A trailing slash is matched as an empty
+    // last segment, resources never have one. Resources are only read, other methods go to the
+    // handler routes. Returns the registered path that matched with the route
+    fn find(
+        &self,
+        request: &ServerRequest,
+        path: &QueryPath,
+        lookup: Lookup,
+    ) -> ServerResult<(RouteMatch<'_>, Vec<String>)> {
+        let fold_case = lookup.fold_case;
+        let method = request.method();
+        let mut is_resource = false;
+        if let (Some(name), false) = (&path.resource, lookup.trailing_slash) {
+            let tokens = path.tokens.iter().cloned().collect::<Vec<_>>();
+            let found = self.routes.get(&tokens, fold_case, |node| {
+                node.get_resource(name, fold_case).is_some()
+            });
+            match found {
+                Some(found) if method == Method::GET => {
+                    let (name, resource) = found.node.get_resource(name, fold_case).unwrap();
+                    let mut path = found.path;
+                    path.push(name.to_string());
+                    return Ok((RouteMatch::Resource(resource), path));
+                }
+                Some(_) => is_resource = true,
+                None => {}
+            }
+        }
+
+        let mut segments = path.segments();
+        if lookup.trailing_slash {
+            segments.push(String::new());
+        }
+        let found = self.routes.get(&segments, fold_case, |node| {
+            node.get_rest(method.clone()).is_some()
+        });
+        if let Some(found) = found {
+            let handler = found.node.get_rest(method.clone()).unwrap();
+            return Ok((RouteMatch::Handler(handler, found.args), found.path));
+        }
+        match self.routes.get(&segments, fold_case, RouteNode::has_rest) {
+            Some(Found { node, .. }) => {
+                let mut methods = node.allowed_methods();
+                if is_resource && !methods.contains(&Method::GET.as_str()) {
+                    methods.insert(0, Method::GET.as_str());
+                }
+                Err(method_not_allowed(&methods))
+            }
+            None if is_resource => Err(method_not_allowed(&[Method::GET.as_str()])),
+            None if path.resource.is_some() => Err(not_found("Resource not found")),
+            None => Err(not_found("Route not found")),
         }
     }
+
+    // Methods the path can be requested with, resources and directories are only read
+    fn methods(&self, path: &QueryPath, lookup: Lookup) -> Option<Vec<&'static str>> {
+        let fold_case = lookup.fold_case;
+        let mut is_resource = false;
+        if let (Some(name), false) = (&path.resource, lookup.trailing_slash) {
+            let tokens = path.tokens.iter().cloned().collect::<Vec<_>>();
+            is_resource = self
+                .routes
+                .get(&tokens, fold_case, |node| {
+                    node.get_resource(name, fold_case).is_some()
+                })
+                .is_some();
+        }
+        let mut segments = path.segments();
+        if lookup.trailing_slash {
+            segments.push(String::new());
+        }
+        match self.routes.get(&segments, fold_case, RouteNode::has_rest) {
+            Some(found) => {
+                let mut methods = found.node.allowed_methods();
+                if is_resource && !methods.contains(&Method::GET.as_str()) {
+                    methods.insert(0, Method::GET.as_str());
+                }
+                Some(methods)
+            }
+            None if is_resource => Some(vec![Method::GET.as_str()]),
+            None => self
+                .routes
+                .get_directory(path, fold_case)
+                .map(|_| vec![Method::GET.as_str()]),
+        }
+    }
+
+    // Route and registered path of a request that only differs from the route by its trailing
+    // slash or its case, when the policy for that difference is to redirect
+    fn canonical(
+        &self,
+        request: &ServerRequest,
+        path: &QueryPath,
+    ) -> Option<(RouteMatch<'_>, String)> {
+        let (trailing_slash, case) = (self.routes.trailing_slash, self.routes.case);
+        if trailing_slash != PathPolicy::Redirect && case != PathPolicy::Redirect {
+            return None;
+        }
+        let slashes = match trailing_slash {
+            PathPolicy::Strict => vec![path.trailing_slash],
+            PathPolicy::Redirect => vec![path.trailing_slash, !path.trailing_slash],
+            PathPolicy::Lenient => vec![false],
+        };
+        slashes.into_iter().find_map(|trailing_slash| {
+            let lookup = Lookup {
+                trailing_slash,
+                fold_case: case != PathPolicy::Strict,
+            };
+            let (found, segments) = self.find(request, path, lookup).ok()?;
+            let segments = segments
+                .iter()
+                .map(|segment| utf8_percent_encode(segment, SEGMENT).to_string())
+                .collect::<Vec<_>>();
+            Some((found, format!("/{}", segments.join("/"))))
+        })
+    }
 }
 
 impl RouteNode {
-    fn get_resource(&self, name: ResourceName) -> Option<&ResourceLocation> {
-        self.resources.get(name)
+    fn get_resource(&self, name: &str, fold_case: bool) -> Option<(&str, &Scoped<FileResource>)> {
+        match self.resources.get_key_value(name) {
+            Some((name, resource)) => Some((name, resource)),
+            None if fold_case => {
+                let name = name.to_lowercase();
+                self.resources
+                    .iter()
+                    .find(|(key, _)| key.to_lowercase() == name)
+                    .map(|(name, resource)| (&**name, resource))
+            }
+            None => None,
+        }
+    }
+
+    fn get_rest(&self, method: Method) -> Option<&Scoped<RequestHandler>> {
+        self.rest[method_as_usize(method)?].as_ref()
+    }
+
+    fn has_rest(&self) -> bool {
+        self.rest.iter().any(Option::is_some)
+    }
+
+    fn allowed_methods(&self) -> Vec<&'static str> {
+        METHODS
+            .iter()
+            .zip(&self.rest)
+            .filter(|(_, handler)| handler.is_some())
+            .map(|(method, _)| method.as_str())
+            .collect()
+    }
+
+    fn get_child_vars<'a: 't, 't>(
+        &'a self,
+        token: &'t str,
+    ) -> impl Iterator<Item = &'a VariableChild> + 't {
+        self.children
+            .variables
+            .iter()
+            .filter(move |child| !token.is_empty() && child.accepts(token))
     }
 
-    fn get_rest(&self, method: Method) -> Option<&RequestHandler> {
-        self.rest[method_as_usize(method)].as_ref()
+    fn get_child_static(&self, token: &str, fold_case: bool) -> Option<(&str, &RouteNode)> {
+        let statics = &self.children.statics;
+        match statics.get_key_value(token) {
+            Some((token, child)) => Some((token, child)),
+            None if fold_case => {
+                let token = self.children.folded.get(&token.to_lowercase())?;
+                statics
+                    .get_key_value(token)
+                    .map(|(token, child)| (&**token, child))
+            }
+            None => None,
+        }
     }
 
-    fn get_child_var(&self) -> Option<(String, RouteNodePointer)> {
-        match &self.children {
-            Some(RouteChildren::Variable(name, ptr)) => Some((name.to_string(), ptr.clone())),
-            _ => None,
+    fn display_route(&self) -> &str {
+        match self.route.as_str() {
+            "" => "/",
+            route => route,
         }
     }
 
-    fn get_child_static(&self, token: &str) -> Option<RouteNodePointer> {
-        match &self.children {
-            Some(RouteChildren::Static(map)) => map.get(token).cloned(),
-            _ => None,
+    fn new_child(&self, token: &RoutePathToken) -> RouteNode {
+        RouteNode {
+            route: format!("{}/{}", self.route, token),
+            ..Default::default()
         }
     }
 
-    fn register_endpoint(ptr: RouteNodePointer, req: NodeEndpoint) -> ServerResult<()> {
-        let mut node = ptr.lock().unwrap();
+    fn register_endpoint(
+        &mut self,
+        req: NodeEndpoint,
+        scope: Arc<Scope>,
+        template: &str,
+        fold_case: bool,
+    ) -> ServerResult<()> {
         match req {
             NodeEndpoint::REST(method, callback) => {
-                Ok(node.rest[method_as_usize(method)] = Some(callback))
+                let route = self.display_route().to_string();
+                let slot = match method_as_usize(method.clone()) {
+                    Some(index) => &mut self.rest[index],
+                    None => {
+                        return Err(ServerError::err(&format!(
+                            "Unsupported method: {} registers {}",
+                            template, method
+                        )))
+                    }
+                };
+                if slot.is_some() {
+                    return Err(ServerError::err(&format!(
+                        "Handler conflict: {} registers {} which {} already handles",
+                        template, method, route
+                    )));
+                }
+                *slot = Some(Scoped {
+                    value: callback,
+                    scope,
+                });
+                Ok(())
+            }
+            NodeEndpoint::Resource(name, resource) => {
+                if self.get_resource(&name, fold_case).is_some() {
+                    return Err(ServerError::err(&format!(
+                        "Resource conflict: {} registers {} which {} already serves",
+                        template,
+                        name,
+                        self.display_route()
+                    )));
+                }
+                self.resources.insert(
+                    name,
+                    Scoped {
+                        value: resource,
+                        scope,
+                    },
+                );
+                Ok(())
+            }
+            NodeEndpoint::Directory(dir) => {
+                if let Some(existing) = &self.directory {
+                    return Err(ServerError::err(&format!(
+                        "Directory conflict: {} mounts {:?} but {} already serves {:?}",
+                        template,
+                        dir.root,
+                        self.display_route(),
+                        existing.value.root
+                    )));
+                }
+                self.directory = Some(Scoped { value: dir, scope });
+                Ok(())
             }
-            NodeEndpoint::Resource(name, loc) => {
-                if let Some(_) = node.resources.get(&name) {
-                    return Err(ServerError::err("Resource Conflict"));
+            NodeEndpoint::Fallback(handler) => {
+                if self.fallback.is_some() {
+                    return Err(ServerError::err(&format!(
+                        "Fallback conflict: {} registers a fallback which {} already has",
+                        template,
+                        self.display_route()
+                    )));
                 }
-                node.resources.insert(name, loc);
+                self.fallback = Some(Scoped {
+                    value: handler,
+                    scope,
+                });
                 Ok(())
             }
         }
     }
 
-    fn register_static_child(
-        ptr: RouteNodePointer,
-        token: &'static str,
-    ) -> ServerResult<RouteNodePointer> {
-        let mut node = ptr.lock().unwrap();
-        if let Some(_) = node.get_child_var() {
-            return Err(ServerError::err("Route Conflict"));
-        }
-        if let None = node.children {
-            node.children = Some(RouteChildren::Static(HashMap::new()));
-        }
-        match node.children.as_mut() {
-            Some(RouteChildren::Static(children)) => {
-                if let Some(_) = children.get(token) {
-                    return Err(ServerError::err("Route Conflict"));
+    // Children are shared between routes, registering an existing child returns it. Variables and
+    // catch-alls must keep the name they were first registered with, static tokens can't differ
+    // only by case when case is folded
+    fn ensure_child(
+        &mut self,
+        token: RoutePathToken,
+        template: &str,
+        fold_case: bool,
+    ) -> ServerResult<&mut RouteNode> {
+        let child = self.new_child(&token);
+        let conflict = |existing: &RouteNode| {
+            ServerError::err(&format!(
+                "Route conflict: {} uses {} where {} is already registered",
+                template,
+                token,
+                existing.display_route()
+            ))
+        };
+        let children = &mut self.children;
+        match &token {
+            RoutePathToken::Static(name) => {
+                let folded = name.to_lowercase();
+                match children.folded.get(&folded) {
+                    Some(existing) if fold_case && existing != name => {
+                        return Err(conflict(&children.statics[existing]))
+                    }
+                    Some(_) => {}
+                    None => {
+                        children.folded.insert(folded, name.clone());
+                    }
+                }
+                Ok(children.statics.entry(name.clone()).or_insert(child))
+            }
+            RoutePathToken::Variable(name, constraint) => {
+                let variables = &mut children.variables;
+                let index = match variables.iter().position(|v| v.same_constraint(constraint)) {
+                    Some(index) if variables[index].name == *name => index,
+                    Some(index) => return Err(conflict(&variables[index].node)),
+                    None => {
+                        let index = match constraint {
+                            Some(_) => variables.partition_point(|v| v.constraint.is_some()),
+                            None => variables.len(),
+                        };
+                        let variable = VariableChild {
+                            name: name.clone(),
+                            constraint: constraint.clone(),
+                            node: child,
+                        };
+                        variables.insert(index, variable);
+                        index
+                    }
+                };
+                Ok(&mut variables[index].node)
+            }
+            RoutePathToken::CatchAll(name) => {
+                if let Some((existing, node)) = &children.catch_all {
+                    if existing != name {
+                        return Err(conflict(node));
+                    }
                 }
-                let ptr = Arc::new(Mutex::new(RouteNode::default()));
-                let _ = children.insert(token, ptr.clone());
-                Ok(ptr)
+                let (_, node) = children
+                    .catch_all
+                    .get_or_insert_with(|| (name.clone(), Box::new(child)));
+                Ok(node)
             }
-            _ => unreachable!(),
+            RoutePathToken::Optional(_) => unreachable!("Optional tokens are expanded first"),
         }
     }
 
-    fn register_variable_child(
-        ptr: RouteNodePointer,
-        token: &'static str,
-    ) -> ServerResult<RouteNodePointer> {
-        let mut node = ptr.lock().unwrap();
-        if let Some(_) = node.children {
-            return Err(ServerError::err("Route Conflict"));
+    // Writes the node and its children in the order they are tried, each child indented below its
+    // parent, with the methods, directory and resources registered on them
+    fn dump(&self, label: &str, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        let mut endpoints = self
+            .allowed_methods()
+            .iter()
+            .map(|method| method.to_string())
+            .collect::<Vec<_>>();
+        if let Some(dir) = &self.directory {
+            endpoints.push(format!("DIR {}", dir.value.root.display()));
+        }
+        if self.fallback.is_some() {
+            endpoints.push("ANY".to_string());
+        }
+        match endpoints.is_empty() {
+            true => out.push_str(&format!("{}{}\n", indent, label)),
+            false => out.push_str(&format!("{}{}  {}\n", indent, label, endpoints.join(" "))),
+        }
+
+        let mut resources = self.resources.iter().collect::<Vec<_>>();
+        resources.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, resource) in resources {
+            out.push_str(&format!(
+                "{}  {} -> {}\n",
+                indent, name, resource.value.location
+            ));
+        }
+        let mut statics = self.children.statics.iter().collect::<Vec<_>>();
+        statics.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, child) in statics {
+            // The empty token is the trailing slash of a route
+            let label = match name.is_empty() {
+                true => "/",
+                false => name,
+            };
+            child.dump(label, depth + 1, out);
+        }
+        for child in &self.children.variables {
+            let label = match &child.constraint {
+                Some(constraint) => format!("[{}:{}]", child.name, constraint.source()),
+                None => format!("[{}]", child.name),
+            };
+            child.node.dump(&label, depth + 1, out);
+        }
+        if let Some((name, child)) = &self.children.catch_all {
+            child.dump(&format!("[...{}]", name), depth + 1, out);
         }
-        let ptr = Arc::new(Mutex::new(RouteNode::default()));
-        node.children = Some(RouteChildren::Variable(token, ptr.clone()));
-        Ok(ptr)
     }
 
-    fn ensure_child(
-        ptr: RouteNodePointer,
-        token: RoutePathToken,
-    ) -> ServerResult<RouteNodePointer> {
-        match token {
-            RoutePathToken::Static(name) => Self::register_static_child(ptr, name),
-            RoutePathToken::Variable(name) => Self::register_variable_child(ptr, name),
+    // Depth first search for a node accepted by the filter, static children are tried before the
+    // variable children whose constraint accepts the segment, the catch-all is the last resort.
+    // The registered path is collected backwards as the search unwinds
+    fn find<'a>(
+        &'a self,
+        segments: &[String],
+        fold_case: bool,
+        filter: &dyn Fn(&RouteNode) -> bool,
+        found: &mut Found<'a>,
+    ) -> bool {
+        let (token, rest) = match segments.split_first() {
+            Some(split) => split,
+            None if filter(self) => {
+                found.node = self;
+                return true;
+            }
+            None => return false,
+        };
+        if let Some((name, child)) = self.get_child_static(token, fold_case) {
+            if child.find(rest, fold_case, filter, found) {
+                found.path.push(name.to_string());
+                return true;
+            }
+        }
+        for child in self.get_child_vars(token) {
+            if child.node.find(rest, fold_case, filter, found) {
+                found.args.insert(&child.name, token.clone());
+                found.path.push(token.clone());
+                return true;
+            }
         }
+        if let Some((name, child)) = &self.children.catch_all {
+            if !token.is_empty() && filter(child) {
+                found.node = child;
+                let rest = segments.iter().filter(|segment| !segment.is_empty());
+                found
+                    .args
+                    .insert(name, rest.cloned().collect::<Vec<_>>().join("/"));
+                found.path.extend(segments.iter().rev().cloned());
+                return true;
+            }
+        }
+        false
     }
 }
 
 impl RouteTree {
-    fn ensure_path(&mut self, path: RoutePath) -> ServerResult<RouteNodePointer> {
-        let mut ptr = self.root.clone();
-        for token in path {
-            ptr = RouteNode::ensure_child(ptr, token)?;
+    fn ensure_path(
+        &mut self,
+        tokens: Vec<RoutePathToken>,
+        template: &str,
+    ) -> ServerResult<&mut RouteNode> {
+        let fold_case = self.case != PathPolicy::Strict;
+        let mut node = &mut self.root;
+        for token in tokens {
+            node = node.ensure_child(token, template, fold_case)?;
+        }
+        Ok(node)
+    }
+
+    // Routes with optional tokens register the endpoint on every variant of the path
+    fn register(
+        &mut self,
+        path: RoutePath,
+        template: &str,
+        request: NodeEndpoint,
+        scope: Arc<Scope>,
+    ) -> ServerResult<()> {
+        let fold_case = self.case != PathPolicy::Strict;
+        for tokens in path.variants() {
+            let node = self.ensure_path(tokens, template)?;
+            node.register_endpoint(request.clone(), scope.clone(), template, fold_case)?;
         }
-        Ok(ptr)
+        Ok(())
     }
 
-    fn register(&mut self, path: &'static str, request: NodeEndpoint) -> ServerResult<()> {
-        println!("Registering path: {:?}", path);
-        let ptr = match path {
-            "/" => self.root.clone(),
-            _ => self.ensure_path(path.try_into()?)?,
+    fn get(
+        &self,
+        segments: &[String],
+        fold_case: bool,
+        filter: impl Fn(&RouteNode) -> bool,
+    ) -> Option<Found<'_>> {
+        let mut found = Found {
+            node: &self.root,
+            args: PathArguments::default(),
+            path: vec![],
         };
-        RouteNode::register_endpoint(ptr, request)
-    }
-
-    pub fn get(&self, path: &QueryPath) -> ServerResult<(RouteNodePointer, PathArguments)> {
-        let mut next = self.root.clone();
-        let mut ptr: RouteNodePointer;
-        let mut args = HashMap::new();
-        for token in &path.tokens {
-            ptr = next;
-            let node = ptr.lock().unwrap();
-            if let Some((name, child)) = node.get_child_var() {
-                args.insert(name, token.clone().to_string());
-                next = child;
-            } else if let Some(child) = node.get_child_static(&token) {
-                next = child;
-            } else {
-                return Err(ServerError::err("Invalid path").log());
+        if !self.root.find(segments, fold_case, &filter, &mut found) {
+            return None;
+        }
+        found.path.reverse();
+        Some(found)
+    }
+
+    // Finds the deepest directory mounted along the path, returning it with the segments that are
+    // left to resolve inside of it
+    fn get_directory(
+        &self,
+        path: &QueryPath,
+        fold_case: bool,
+    ) -> Option<(&Scoped<StaticDir>, Vec<String>)> {
+        let segments = path.segments();
+        self.deepest(&segments, fold_case, |node| node.directory.as_ref())
+            .map(|(dir, depth)| (dir, segments[depth..].to_vec()))
+    }
+
+    // Finds the fallback of the deepest prefix along the path that has one
+    fn get_fallback(&self, path: &QueryPath, fold_case: bool) -> Option<&Scoped<RequestHandler>> {
+        self.deepest(&path.segments(), fold_case, |node| node.fallback.as_ref())
+            .map(|(fallback, _)| fallback)
+    }
+
+    // Walks the path through static children, then the first variable accepting the segment, and
+    // returns the last endpoint found on the way with the number of segments it was found after
+    fn deepest<'a, T>(
+        &'a self,
+        segments: &[String],
+        fold_case: bool,
+        endpoint: impl Fn(&'a RouteNode) -> Option<&'a T>,
+    ) -> Option<(&'a T, usize)> {
+        let mut node = &self.root;
+        let mut found = endpoint(node).map(|value| (value, 0));
+        for (depth, token) in segments.iter().enumerate() {
+            node = match node.get_child_static(token, fold_case) {
+                Some((_, child)) => child,
+                None => match node.get_child_vars(token).next() {
+                    Some(child) => &child.node,
+                    None => break,
+                },
+            };
+            if let Some(value) = endpoint(node) {
+                found = Some((value, depth + 1));
             }
         }
-        Ok((next, args))
+        found
     }
 }
 
-#[derive(Default, Clone)]
+// A route waiting to be compiled into the tree, the prefixes of the groups it was nested in come
+// first in its path and their middleware first in its layers. Mime types of the inner groups come
+// last so they override the outer ones
+#[derive(Debug, Clone)]
+struct RouteEntry {
+    path: Vec<RouteText>,
+    endpoint: NodeEndpoint,
+    layers: Vec<Middleware>,
+    auth: Option<AuthPolicy>,
+    mime_types: Vec<MimeOverride>,
+    name: Option<RouteText>,
+    #[cfg(feature = "json")]
+    doc: Option<RouteDoc>,
+}
+
+// Content type and disposition used for the files with an extension
+type MimeOverride = (String, String, Disposition);
+
+// Routes are only compiled into the tree when the router is built, so builders can be nested into
+// each other in any order and conflicts between them are reported by build. Calls that can't be
+// applied when they are made are reported by build as well
+#[derive(Default, Debug, Clone)]
 pub struct RouterBuilder {
-    tree: RouteTree,
+    routes: Vec<RouteEntry>,
+    errors: Vec<ServerError>,
+    layers: Vec<Middleware>,
+    auth: Option<AuthPolicy>,
+    mime_types: Vec<MimeOverride>,
+    trailing_slash: Option<PathPolicy>,
+    case: Option<PathPolicy>,
+    cors: Option<Cors>,
+    #[cfg(feature = "json")]
+    openapi: Option<ApiInfo>,
 }
 
 #[allow(dead_code)]
@@ -215,62 +1058,1103 @@ impl RouterBuilder {
         Self::default()
     }
 
+    fn add(&mut self, path: impl Into<RouteText>, endpoint: NodeEndpoint) -> &mut Self {
+        self.routes.push(RouteEntry {
+            path: vec![path.into()],
+            endpoint,
+            layers: vec![],
+            auth: None,
+            mime_types: vec![],
+            name: None,
+            #[cfg(feature = "json")]
+            doc: None,
+        });
+        self
+    }
+
     fn rest(
         &mut self,
-        path: &'static str,
+        path: impl Into<RouteText>,
         method: Method,
         handler: RequestHandler,
-    ) -> ServerResult<&mut Self> {
-        match self
-            .tree
-            .register(path, NodeEndpoint::REST(method, handler))
-        {
-            Ok(_) => Ok(self),
-            Err(e) => Err(e),
-        }
+    ) -> &mut Self {
+        self.add(path, NodeEndpoint::REST(method, handler))
     }
 
-    pub fn get(&mut self, path: &'static str, handler: RequestHandler) -> &mut Self {
-        self.rest(path, Method::GET, handler).unwrap()
+    pub fn get(&mut self, path: impl Into<RouteText>, handler: RequestHandler) -> &mut Self {
+        self.rest(path, Method::GET, handler)
     }
 
-    pub fn post(&mut self, path: &'static str, handler: RequestHandler) -> &mut Self {
-        self.rest(path, Method::POST, handler).unwrap()
+    pub fn post(&mut self, path: impl Into<RouteText>, handler: RequestHandler) -> &mut Self {
+        self.rest(path, Method::POST, handler)
     }
 
-    pub fn put(&mut self, path: &'static str, handler: RequestHandler) -> &mut Self {
-        self.rest(path, Method::PUT, handler).unwrap()
+    pub fn put(&mut self, path: impl Into<RouteText>, handler: RequestHandler) -> &mut Self {
+        self.rest(path, Method::PUT, handler)
     }
 
-    pub fn delete(&mut self, path: &'static str, handler: RequestHandler) -> &mut Self {
-        self.rest(path, Method::DELETE, handler).unwrap()
+    pub fn delete(&mut self, path: impl Into<RouteText>, handler: RequestHandler) -> &mut Self {
+        self.rest(path, Method::DELETE, handler)
     }
 
     pub fn resource(
         &mut self,
-        path: &'static str,
-        name: ResourceName<'static>,
-        loc: ResourceLocation<'static>,
+        path: impl Into<RouteText>,
+        name: impl Into<ResourceName>,
+        loc: impl Into<ResourceLocation>,
     ) -> &mut Self {
-        self.tree
-            .register(path, NodeEndpoint::Resource(name, loc))
-            .unwrap();
+        self.register_resource(path, name.into(), loc.into(), None)
+    }
+
+    // Same as resource, but always served with the given disposition regardless of its type
+    pub fn resource_as(
+        &mut self,
+        path: impl Into<RouteText>,
+        name: impl Into<ResourceName>,
+        loc: impl Into<ResourceLocation>,
+        disposition: Disposition,
+    ) -> &mut Self {
+        self.register_resource(path, name.into(), loc.into(), Some(disposition))
+    }
+
+    fn register_resource(
+        &mut self,
+        path: impl Into<RouteText>,
+        name: ResourceName,
+        location: ResourceLocation,
+        disposition: Option<Disposition>,
+    ) -> &mut Self {
+        let resource = FileResource {
+            location,
+            disposition,
+        };
+        self.add(path, NodeEndpoint::Resource(name, resource))
+    }
+
+    // Adds or replaces the content type used for files with the given extension, for the files
+    // served by the routes of this builder and the ones nested in it
+    pub fn mime_type(
+        &mut self,
+        extension: &str,
+        content_type: &str,
+        disposition: Disposition,
+    ) -> &mut Self {
+        self.mime_types
+            .push((extension.to_string(), content_type.to_string(), disposition));
+        self
+    }
+
+    pub fn static_dir(&mut self, prefix: impl Into<RouteText>, directory: &str) -> &mut Self {
+        self.mount(prefix, StaticDir::new(directory))
+    }
+
+    pub fn mount(&mut self, prefix: impl Into<RouteText>, dir: StaticDir) -> &mut Self {
+        self.add(prefix, NodeEndpoint::Directory(dir))
+    }
+
+    // Middleware wrapping every route of this builder, including the nested ones. Layers run in
+    // the order they were added, before the layers of nested builders
+    pub fn layer(&mut self, middleware: Middleware) -> &mut Self {
+        self.layers.push(middleware);
+        self
+    }
+
+    // Auth policy of the routes of this builder, nested builders with their own policy keep it.
+    // Routes default to requiring authentication
+    pub fn auth(&mut self, policy: AuthPolicy) -> &mut Self {
+        self.auth = Some(policy);
+        self
+    }
+
+    // Mounts every route of another builder below the prefix, with its middleware, auth policy,
+    // mime types and fallback. CORS, path policies and the OpenAPI document apply to the whole
+    // router, build reports them as errors when they are set on a nested builder
+    pub fn nest(&mut self, prefix: impl Into<RouteText>, router: RouterBuilder) -> &mut Self {
+        let prefix = prefix.into();
+        let RouterBuilder {
+            routes,
+            errors,
+            layers,
+            auth,
+            mime_types,
+            trailing_slash,
+            case,
+            cors,
+            #[cfg(feature = "json")]
+            openapi,
+        } = router;
+        for mut entry in routes {
+            entry.path.insert(0, prefix.clone());
+            entry.layers = layers.iter().chain(&entry.layers).copied().collect();
+            entry.auth = entry.auth.or(auth.clone());
+            entry.mime_types = mime_types
+                .iter()
+                .chain(&entry.mime_types)
+                .cloned()
+                .collect();
+            self.routes.push(entry);
+        }
+        self.errors.extend(errors);
+        let policies = [("trailing_slash", trailing_slash), ("case", case)];
+        for (setting, _) in policies.iter().filter(|(_, policy)| policy.is_some()) {
+            self.errors.push(ServerError::err(&format!(
+                "Misplaced {}: {} sets a path policy, only the root router can",
+                setting, prefix
+            )));
+        }
+        if cors.is_some() {
+            self.errors.push(ServerError::err(&format!(
+                "Misplaced cors: {} allows cross-origin requests, only the root router can",
+                prefix
+            )));
+        }
+        #[cfg(feature = "json")]
+        if openapi.is_some() {
+            self.errors.push(ServerError::err(&format!(
+                "Misplaced openapi: {} serves a document, only the root router can",
+                prefix
+            )));
+        }
+        self
+    }
+
+    // Names the route added last, so links to it can be built with url_for. Names are kept when
+    // the route is nested and must be unique across the router
+    pub fn name(&mut self, name: impl Into<RouteText>) -> &mut Self {
+        let name = name.into();
+        match self.routes.last_mut() {
+            Some(entry) => entry.name = Some(name),
+            None => self.errors.push(ServerError::err(&format!(
+                "Misplaced name: {} is given before any route",
+                name
+            ))),
+        }
+        self
+    }
+
+    // Handles the requests that don't match any route instead of answering them with a 404, it
+    // runs with the middleware and auth policy of this builder. A nested builder's fallback only
+    // handles the requests below its prefix, the deepest fallback along the path is used
+    pub fn fallback(&mut self, handler: RequestHandler) -> &mut Self {
+        self.add("/", NodeEndpoint::Fallback(handler))
+    }
+
+    // Allows cross-origin requests to every route of the router, preflights are answered from the
+    // methods each route handles. Only the root builder can set it, nested builders share its policy
+    pub fn cors(&mut self, cors: Cors) -> &mut Self {
+        self.cors = Some(cors);
+        self
+    }
+
+    // Policy for requests that only differ from a route by a trailing slash, routes are registered
+    // with or without one. Lenient by default, where /api and /api/ are the same route. The policy
+    // applies to the whole router, so only the root builder can set it
+    pub fn trailing_slash(&mut self, policy: PathPolicy) -> &mut Self {
+        self.trailing_slash = Some(policy);
+        self
+    }
+
+    // Policy for requests that only differ from a route by the case of its static tokens and
+    // resource names, variables always keep the case they were sent with. Strict by default, and
+    // like the trailing slash policy only set on the root builder
+    pub fn case(&mut self, policy: PathPolicy) -> &mut Self {
+        self.case = Some(policy);
+        self
+    }
+
+    // Documents the route added last in the OpenAPI document
+    #[cfg(feature = "json")]
+    pub fn doc(&mut self, doc: RouteDoc) -> &mut Self {
+        match self.routes.last_mut() {
+            Some(entry) => entry.doc = Some(doc),
+            None => self.errors.push(ServerError::err(&format!(
+                "Misplaced doc: {} is given before any route",
+                doc.summary.as_deref().unwrap_or("documentation")
+            ))),
+        }
         self
     }
 
-    pub fn build(&self) -> Router {
-        Router {
-            routes: self.tree.clone(),
+    // Serves the OpenAPI document of every handler route of the router at the path. The document
+    // covers the whole router, so only the root builder can serve it
+    #[cfg(feature = "json")]
+    pub fn openapi(&mut self, path: impl Into<RouteText>, info: ApiInfo) -> &mut Self {
+        self.openapi = Some(info);
+        self.get(path, serve_openapi)
+    }
+
+    // Serves the route listing and the tree dump as text, the endpoint needs authentication like
+    // any other route unless it is registered in a group with another policy
+    pub fn debug_endpoint(&mut self, path: impl Into<RouteText>) -> &mut Self {
+        self.get(path, list_routes)
+    }
+
+    // Removes every route registered at the path, the path must be written the same way it was
+    // registered, with the prefixes of the groups it was nested in
+    pub fn remove(&mut self, path: impl Into<RouteText>) -> &mut Self {
+        let path = template(&[path.into()]);
+        self.routes.retain(|entry| template(&entry.path) != path);
+        self
+    }
+
+    // Same as nest, with the routes of the group registered by the closure
+    pub fn group(
+        &mut self,
+        prefix: impl Into<RouteText>,
+        routes: impl FnOnce(&mut Self),
+    ) -> &mut Self {
+        let mut group = RouterBuilder::new();
+        routes(&mut group);
+        self.nest(prefix, group)
+    }
+
+    // Every route is registered even after one fails, so all invalid paths and conflicts are
+    // reported together
+    pub fn build(&self) -> Result<Router, Vec<ServerError>> {
+        let mut tree = RouteTree {
+            trailing_slash: self.trailing_slash.unwrap_or(PathPolicy::Lenient),
+            case: self.case.unwrap_or(PathPolicy::Strict),
+            ..Default::default()
+        };
+        let mut table = RouteTable::default();
+        let mut errors = self.errors.clone();
+        if let Some(Err(e)) = self.cors.as_ref().map(Cors::validate) {
+            errors.push(e);
         }
+        // Routes of the same group share their mime table
+        let mut mime_tables: Vec<(&[MimeOverride], Arc<MimeTypes>)> = vec![];
+        for entry in &self.routes {
+            let mime = match mime_tables
+                .iter()
+                .find(|(types, _)| *types == entry.mime_types)
+            {
+                Some((_, mime)) => mime.clone(),
+                None => {
+                    let mime = Arc::new(self.mime_table(&entry.mime_types));
+                    mime_tables.push((&entry.mime_types, mime.clone()));
+                    mime
+                }
+            };
+            if let Err(e) = self.register(&mut tree, &mut table, entry, mime) {
+                errors.push(e);
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        tree.root.dump("/", 0, &mut table.tree);
+        #[cfg(feature = "json")]
+        if let Some(info) = &self.openapi {
+            table.openapi = Some(openapi::document(info, &table.routes));
+        }
+        Ok(Router {
+            routes: tree,
+            table: Arc::new(table),
+            cors: self.cors.clone(),
+            source: self.clone(),
+        })
+    }
+
+    // The default table with the mime types of this builder, then the ones of the groups the route
+    // was nested in
+    fn mime_table(&self, nested: &[MimeOverride]) -> MimeTypes {
+        let mut mime = MimeTypes::default();
+        for (extension, content_type, disposition) in self.mime_types.iter().chain(nested) {
+            mime.insert(extension, content_type, *disposition);
+        }
+        mime
+    }
+
+    fn register(
+        &self,
+        tree: &mut RouteTree,
+        table: &mut RouteTable,
+        entry: &RouteEntry,
+        mime: Arc<MimeTypes>,
+    ) -> ServerResult<()> {
+        let template = template(&entry.path);
+        let invalid =
+            |e: ServerError| ServerError::err(&format!("Invalid route {}: {}", template, e.error));
+        let mut path = RoutePath::default();
+        for part in &entry.path {
+            let part = RoutePath::try_from(part.clone()).map_err(invalid)?;
+            path = path.join(part).map_err(invalid)?;
+        }
+        if tree.trailing_slash != PathPolicy::Lenient && trailing_slash(&entry.path) {
+            path = path.trailing_slash();
+        }
+        if let Some(name) = &entry.name {
+            if let Some(existing) = table.names.get(&**name) {
+                return Err(ServerError::err(&format!(
+                    "Name conflict: {} is named {} which {} already uses",
+                    template, name, existing.template
+                )));
+            }
+            let route = NamedRoute {
+                template: template.clone(),
+                path: path.clone(),
+            };
+            table.names.insert(name.to_string(), route);
+        }
+        let scope = Scope {
+            layers: self.layers.iter().chain(&entry.layers).copied().collect(),
+            auth: entry.auth.clone().or(self.auth.clone()).unwrap_or_default(),
+            mime,
+        };
+        let endpoint = match &entry.endpoint {
+            NodeEndpoint::REST(method, _) => RouteEndpoint::Handler(method.clone()),
+            NodeEndpoint::Resource(name, resource) => RouteEndpoint::Resource {
+                name: name.to_string(),
+                location: resource.location.to_string(),
+                disposition: resource.disposition,
+            },
+            NodeEndpoint::Directory(dir) => RouteEndpoint::Directory {
+                root: dir.root.clone(),
+                listing: dir.listing,
+            },
+            NodeEndpoint::Fallback(_) => RouteEndpoint::Fallback,
+        };
+        let info = RouteInfo {
+            template: template.clone(),
+            endpoint,
+            name: entry.name.as_ref().map(|name| name.to_string()),
+            auth: scope.auth.clone(),
+            layers: scope.layers.len(),
+            #[cfg(feature = "json")]
+            doc: entry.doc.clone(),
+        };
+        tree.register(path, &template, entry.endpoint.clone(), Arc::new(scope))?;
+        table.routes.push(info);
+        Ok(())
+    }
+}
+
+fn list_routes(request: ServerRequest, _: PathArguments) -> ServerResult<ServerResponse> {
+    let table = request.routes();
+    let routes = table
+        .routes()
+        .iter()
+        .map(|route| format!("{}\n", route))
+        .collect::<String>();
+    Ok(ServerResponse::create(
+        StatusCode::OK,
+        format!("{}\n{}", routes, table.tree()),
+    ))
+}
+
+#[cfg(feature = "json")]
+fn serve_openapi(request: ServerRequest, _: PathArguments) -> ServerResult<ServerResponse> {
+    match request.routes().openapi() {
+        Some(document) => ServerResponse::json_value(document),
+        None => Err(ServerError::err("No OpenAPI document")),
+    }
+}
+
+fn not_found(message: &str) -> ServerError {
+    ServerError::new(StatusCode::NOT_FOUND, message)
+}
+
+fn method_not_allowed(methods: &[&str]) -> ServerError {
+    let allow = HeaderValue::from_str(&methods.join(", ")).unwrap();
+    ServerError::new(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed").with_header(ALLOW, allow)
+}
+
+pub(super) fn redirect(location: &str, query: &str) -> ServerResult<ServerResponse> {
+    let location = match query.is_empty() {
+        true => location.to_string(),
+        false => format!("{}?{}", location, query),
+    };
+    let location = HeaderValue::from_str(&location)
+        .map_err(|_| ServerError::err(&format!("Invalid redirect: {}", location)))?;
+    let mut response = ServerResponse::create(StatusCode::PERMANENT_REDIRECT, "");
+    response.headers_mut().insert(LOCATION, location);
+    Ok(response)
+}
+
+// Full template of a nested route, for messages
+fn template(parts: &[RouteText]) -> String {
+    let segments = parts
+        .iter()
+        .flat_map(|part| part.split('/'))
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+    match trailing_slash(parts) && !segments.is_empty() {
+        true => format!("/{}/", segments.join("/")),
+        false => format!("/{}", segments.join("/")),
     }
 }
 
-fn method_as_usize(method: Method) -> usize {
+// A route ends with a slash when its own path does, the prefixes it was nested in don't count
+fn trailing_slash(parts: &[RouteText]) -> bool {
+    parts
+        .last()
+        .is_some_and(|part| part.len() > 1 && part.ends_with('/'))
+}
+
+// Methods in the order of their handler slots
+const METHODS: [Method; 4] = [Method::GET, Method::POST, Method::PUT, Method::DELETE];
+
+fn method_as_usize(method: Method) -> Option<usize> {
     match method {
-        Method::GET => 0,
-        Method::POST => 1,
-        Method::PUT => 2,
-        Method::DELETE => 3,
-        _ => unimplemented!("Only GET/POST/PUT/DELETE supported"),
+        Method::GET => Some(0),
+        Method::POST => Some(1),
+        Method::PUT => Some(2),
+        Method::DELETE => Some(3),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Request;
+
+    use super::*;
+    use crate::server::{
+        auth::{AuthBuilder, Authentication},
+        response::ResponseBody,
+    };
+
+    fn args(_: ServerRequest, args: PathArguments) -> ServerResult<ServerResponse> {
+        let mut args = args.into_iter().collect::<Vec<_>>();
+        args.sort();
+        Ok(ServerResponse::create(
+            StatusCode::OK,
+            format!("{:?}", args),
+        ))
+    }
+
+    fn auth() -> AuthManager {
+        AuthBuilder::new()
+            .allow_user(Authentication::new("user", "pass"))
+            .build()
+    }
+
+    fn request(method: Method, uri: &str, headers: &[(&str, &str)]) -> ServerRequest {
+        let mut request = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        ServerRequest::new(request.body(None).unwrap())
+    }
+
+    fn get(router: &Router, path: &str) -> ServerResult<ServerResponse> {
+        let uri = format!("{}?username=user&password=pass", path);
+        router.resolve(request(Method::GET, &uri, &[]), &auth())
+    }
+
+    fn body(response: ServerResponse) -> String {
+        match response.into_body() {
+            Some(ResponseBody::Bytes(bytes)) => String::from_utf8(bytes).unwrap(),
+            _ => panic!("Expected an in memory body"),
+        }
+    }
+
+    #[test]
+    fn adjacent_optionals_register() {
+        let mut builder = RouterBuilder::new();
+        builder.get("/a/[x?]/[y?]", args);
+        let router = builder.build().unwrap();
+        assert_eq!(body(get(&router, "/a").unwrap()), "[]");
+        assert_eq!(body(get(&router, "/a/1").unwrap()), r#"[("x", "1")]"#);
+        assert_eq!(
+            body(get(&router, "/a/1/2").unwrap()),
+            r#"[("x", "1"), ("y", "2")]"#
+        );
+    }
+
+    #[test]
+    fn constraints_fall_through_to_other_variables() {
+        let mut builder = RouterBuilder::new();
+        builder
+            .get("/devices/[slug]", args)
+            .get("/devices/[id:u32]", args)
+            .get("/devices/[mac:([0-9a-f]{2}:){5}[0-9a-f]{2}]", args)
+            .get("/sensors/[id:u8]/state", args);
+        let router = builder.build().unwrap();
+        assert_eq!(
+            body(get(&router, "/devices/42").unwrap()),
+            r#"[("id", "42")]"#
+        );
+        assert_eq!(
+            body(get(&router, "/devices/4294967296").unwrap()),
+            r#"[("slug", "4294967296")]"#
+        );
+        assert_eq!(
+            body(get(&router, "/devices/0a:1b:2c:3d:4e:5f").unwrap()),
+            r#"[("mac", "0a:1b:2c:3d:4e:5f")]"#
+        );
+        assert_eq!(
+            body(get(&router, "/devices/lamp").unwrap()),
+            r#"[("slug", "lamp")]"#
+        );
+        // Without an unconstrained variable, values the constraint rejects don't match
+        assert_eq!(status(&router, "/sensors/7/state"), StatusCode::OK);
+        assert_eq!(status(&router, "/sensors/256/state"), StatusCode::NOT_FOUND);
+        assert_eq!(status(&router, "/sensors/-1/state"), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn invalid_constraints_fail_the_build() {
+        let mut builder = RouterBuilder::new();
+        builder.get("/devices/[id:(]", args);
+        assert_eq!(builder.build().unwrap_err().len(), 1);
+    }
+
+    #[test]
+    fn parses_typed_arguments() {
+        let mut args = PathArguments::default();
+        args.insert("id", "42".to_string());
+        args.insert("name", "lamp".to_string());
+        assert_eq!(args.parse::<u32>("id").unwrap(), 42);
+        assert_eq!(args.parse_optional::<u32>("page").unwrap(), None);
+        let error = args.parse::<u32>("name").unwrap_err();
+        assert_eq!(error.code(), StatusCode::BAD_REQUEST);
+        assert_eq!(error.public_message(), "Invalid path argument: name");
+        let error = args.parse::<u32>("page").unwrap_err();
+        assert_eq!(error.code(), StatusCode::BAD_REQUEST);
+        assert_eq!(error.public_message(), "Missing path argument: page");
+    }
+
+    #[test]
+    fn collects_every_registration_error() {
+        let mut builder = RouterBuilder::new();
+        builder
+            .name("orphan")
+            .get("/devices/[id]", args)
+            .name("device")
+            .get("/devices/[id]", args)
+            .get("/devices/[name]/edit", args)
+            .get("/devices/[id", args)
+            .get("/files/[...rest]/more", args)
+            .get("/other", args)
+            .name("device");
+        let errors = builder.build().unwrap_err();
+        let errors = errors.iter().map(|e| e.error.as_str()).collect::<Vec<_>>();
+        assert_eq!(errors.len(), 6);
+        assert_eq!(
+            errors[0],
+            "Misplaced name: orphan is given before any route"
+        );
+        assert_eq!(
+            errors[1],
+            "Handler conflict: /devices/[id] registers GET which /devices/[id] already handles"
+        );
+        assert_eq!(
+            errors[2],
+            "Route conflict: /devices/[name]/edit uses [name] where /devices/[id] is already registered"
+        );
+        assert!(errors[3].starts_with("Invalid route /devices/[id: "));
+        assert!(errors[4].starts_with("Invalid route /files/[...rest]/more: "));
+        assert_eq!(
+            errors[5],
+            "Name conflict: /other is named device which /devices/[id] already uses"
+        );
+    }
+
+    #[test]
+    fn nested_errors_are_reported_by_the_parent() {
+        let mut api = RouterBuilder::new();
+        api.name("early").get("/[id", args);
+        let mut builder = RouterBuilder::new();
+        builder.nest("/api", api);
+        let errors = builder.build().unwrap_err();
+        assert_eq!(
+            errors[0].error,
+            "Misplaced name: early is given before any route"
+        );
+        assert!(errors[1].error.starts_with("Invalid route /api/[id: "));
+    }
+
+    #[test]
+    fn builds_links_to_named_routes() {
+        let mut builder = RouterBuilder::new();
+        builder
+            .get("/devices/[id:u32]/[tab?]", args)
+            .name("device")
+            .get("/files/[...path]", args)
+            .name("file");
+        let router = builder.build().unwrap();
+        assert_eq!(router.url_for("device", [("id", 7)]).unwrap(), "/devices/7");
+        assert_eq!(
+            router
+                .url_for("device", [("id", "7"), ("tab", "état civil")])
+                .unwrap(),
+            "/devices/7/%C3%A9tat%20civil"
+        );
+        assert_eq!(
+            router.url_for("file", [("path", "/a b/c.txt")]).unwrap(),
+            "/files/a%20b/c.txt"
+        );
+        let error = |name: &str, args: &[(&str, &str)]| {
+            router
+                .url_for(name, args.iter().copied())
+                .unwrap_err()
+                .error
+        };
+        assert_eq!(error("devices", &[]), "Unknown route name: devices");
+        assert_eq!(
+            error("device", &[]),
+            "Invalid link to device (/devices/[id:u32]/[tab?]): Missing argument: id"
+        );
+        assert_eq!(
+            error("device", &[("id", "7"), ("page", "2")]),
+            "Invalid link to device (/devices/[id:u32]/[tab?]): Unknown argument: page"
+        );
+        assert_eq!(
+            error("device", &[("id", "lamp")]),
+            "Invalid link to device (/devices/[id:u32]/[tab?]): Invalid argument: id"
+        );
+        assert_eq!(
+            error("file", &[("path", "a/../b")]),
+            "Invalid link to file (/files/[...path]): Invalid argument: path"
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn serves_the_openapi_document_from_the_root() {
+        let mut builder = RouterBuilder::new();
+        builder
+            .openapi("/openapi.json", ApiInfo::new("Devices", "1.0"))
+            .get("/devices/[id:u32]", args)
+            .doc(RouteDoc::new("Device"));
+        let router = builder.build().unwrap();
+        let document: serde_json::Value =
+            serde_json::from_str(&body(get(&router, "/openapi.json").unwrap())).unwrap();
+        assert_eq!(document["info"]["title"], "Devices");
+        assert_eq!(
+            document["paths"]["/devices/{id}"]["get"]["summary"],
+            "Device"
+        );
+        assert_eq!(Some(&document), router.table.openapi());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn rejects_nested_openapi_documents_and_misplaced_docs() {
+        let mut api = RouterBuilder::new();
+        api.doc(RouteDoc::new("Devices"))
+            .openapi("/openapi.json", ApiInfo::new("Devices", "1.0"))
+            .get("/devices", args);
+        let mut builder = RouterBuilder::new();
+        builder.nest("/api", api);
+        let errors = builder.build().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].error,
+            "Misplaced doc: Devices is given before any route"
+        );
+        assert_eq!(
+            errors[1].error,
+            "Misplaced openapi: /api serves a document, only the root router can"
+        );
+    }
+
+    #[test]
+    fn files_are_only_read() {
+        let mut builder = RouterBuilder::new();
+        builder
+            .static_dir("/static", "/tmp/static")
+            .resource("/res", "a.txt", "/tmp/a.txt")
+            .post("/res/[name]", args);
+        let router = builder.build().unwrap();
+        let resolve = |method: Method, path: &str| {
+            let uri = format!("{}?username=user&password=pass", path);
+            router.resolve(request(method, &uri, &[]), &auth())
+        };
+        for (method, path, allow) in [
+            (Method::POST, "/static/app.css", "GET"),
+            (Method::DELETE, "/static/", "GET"),
+            (Method::DELETE, "/res/a.txt", "GET, POST"),
+        ] {
+            let error = resolve(method, path).unwrap_err();
+            assert_eq!(error.code(), StatusCode::METHOD_NOT_ALLOWED, "{}", path);
+            assert_eq!(error.headers(), [(ALLOW, HeaderValue::from_static(allow))]);
+        }
+        // Handler routes on the same path still get the other methods
+        let response = resolve(Method::POST, "/res/a.txt").unwrap();
+        assert_eq!(body(response), r#"[("name", "a.txt")]"#);
+        let response = resolve(Method::OPTIONS, "/static/app.css").unwrap();
+        assert_eq!(response.headers()[ALLOW], "GET, OPTIONS");
+        let response = resolve(Method::OPTIONS, "/res/a.txt").unwrap();
+        assert_eq!(response.headers()[ALLOW], "GET, POST, OPTIONS");
+    }
+
+    fn outer(
+        request: ServerRequest,
+        args: PathArguments,
+        next: Next<'_>,
+    ) -> ServerResult<ServerResponse> {
+        let mut response = next.run(request, args)?;
+        let layer = HeaderValue::from_static("outer");
+        response.headers_mut().append("X-Layer", layer);
+        Ok(response)
+    }
+
+    fn inner(
+        request: ServerRequest,
+        args: PathArguments,
+        next: Next<'_>,
+    ) -> ServerResult<ServerResponse> {
+        let mut response = next.run(request, args)?;
+        let layer = HeaderValue::from_static("inner");
+        response.headers_mut().append("X-Layer", layer);
+        Ok(response)
+    }
+
+    fn layers(response: &ServerResponse) -> Vec<&str> {
+        let layers = response.headers().get_all("X-Layer").iter();
+        layers.map(|value| value.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn nests_routes_with_their_layers_and_auth() {
+        let mut api = RouterBuilder::new();
+        api.layer(inner).get("/devices/[id]", args);
+        let mut builder = RouterBuilder::new();
+        builder
+            .layer(outer)
+            .auth(AuthPolicy::Public)
+            .get("/", args)
+            .nest("/api/v1", api)
+            .group("/admin", |admin| {
+                admin
+                    .auth(AuthPolicy::users(&["admin"]))
+                    .get("/users", args);
+            });
+        let router = builder.build().unwrap();
+
+        // Layers append on the way out, so the innermost one comes first
+        let response = get(&router, "/api/v1/devices/5").unwrap();
+        assert_eq!(layers(&response), ["inner", "outer"]);
+        assert_eq!(body(response), r#"[("id", "5")]"#);
+        let response = get(&router, "/").unwrap();
+        assert_eq!(layers(&response), ["outer"]);
+        assert_eq!(status(&router, "/devices/5"), StatusCode::NOT_FOUND);
+
+        // Nested routes without a policy inherit it, the others keep their own
+        let public = request(Method::GET, "/api/v1/devices/5", &[]);
+        assert!(router.resolve(public, &auth()).is_ok());
+        let error = get(&router, "/admin/users").unwrap_err();
+        assert_eq!(error.code(), StatusCode::FORBIDDEN);
+        let info = router
+            .routes()
+            .iter()
+            .map(|r| r.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            info,
+            [
+                "GET     / auth=public layers=1",
+                "GET     /api/v1/devices/[id] auth=public layers=2",
+                "GET     /admin/users auth=users(admin) layers=1",
+            ]
+        );
+    }
+
+    fn root_fallback(_: ServerRequest, _: PathArguments) -> ServerResult<ServerResponse> {
+        Ok(ServerResponse::create(StatusCode::NOT_FOUND, "root"))
+    }
+
+    fn api_fallback(_: ServerRequest, _: PathArguments) -> ServerResult<ServerResponse> {
+        Ok(ServerResponse::create(StatusCode::NOT_FOUND, "api"))
+    }
+
+    #[test]
+    fn fallbacks_only_handle_their_prefix() {
+        let mut api = RouterBuilder::new();
+        api.layer(inner)
+            .auth(AuthPolicy::Public)
+            .get("/devices/[id]", args)
+            .fallback(api_fallback);
+        let mut builder = RouterBuilder::new();
+        builder
+            .layer(outer)
+            .fallback(root_fallback)
+            .nest("/api", api)
+            .get("/status", args);
+        let router = builder.build().unwrap();
+
+        let response = get(&router, "/nothing").unwrap();
+        assert_eq!(layers(&response), ["outer"]);
+        assert_eq!(body(response), "root");
+        let response = get(&router, "/api/nothing").unwrap();
+        assert_eq!(layers(&response), ["inner", "outer"]);
+        assert_eq!(body(response), "api");
+        assert_eq!(body(get(&router, "/api/devices/5/state").unwrap()), "api");
+        // The nested fallback keeps the public policy of its builder, the root one doesn't
+        let anonymous = |path: &str| router.resolve(request(Method::GET, path, &[]), &auth());
+        assert_eq!(body(anonymous("/api/nothing").unwrap()), "api");
+        assert_eq!(
+            anonymous("/nothing").unwrap_err().code(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Without a root fallback, paths outside of the prefix are still missing
+        let mut api = RouterBuilder::new();
+        api.fallback(api_fallback);
+        let mut builder = RouterBuilder::new();
+        builder.nest("/api", api);
+        let router = builder.build().unwrap();
+        assert_eq!(status(&router, "/nothing"), StatusCode::NOT_FOUND);
+        assert_eq!(body(get(&router, "/api/nothing").unwrap()), "api");
+    }
+
+    #[test]
+    fn rejects_two_fallbacks_for_a_prefix() {
+        let mut api = RouterBuilder::new();
+        api.fallback(api_fallback);
+        let mut builder = RouterBuilder::new();
+        builder.group("/api", |api| {
+            api.fallback(root_fallback);
+        });
+        builder.nest("/api", api);
+        let errors = builder.build().unwrap_err();
+        assert_eq!(
+            errors[0].error,
+            "Fallback conflict: /api registers a fallback which /api already has"
+        );
+    }
+
+    #[test]
+    fn detects_conflicts_between_mounted_routers() {
+        let mut lights = RouterBuilder::new();
+        lights.get("/[id]", args).get("/[id]/state", args);
+        let mut switches = RouterBuilder::new();
+        switches.get("/[id]", args).get("/[name]/toggle", args);
+        let mut builder = RouterBuilder::new();
+        builder.nest("/devices", lights).nest("/devices", switches);
+        let errors = builder.build().unwrap_err();
+        let errors = errors.iter().map(|e| e.error.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                "Handler conflict: /devices/[id] registers GET which /devices/[id] already handles",
+                "Route conflict: /devices/[name]/toggle uses [name] where /devices/[id] is already registered",
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_mime_types_only_apply_to_nested_routes() {
+        let dir = std::env::temp_dir().join(format!("router-mime-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["a.dat", "b.dat", "c.log"] {
+            std::fs::write(dir.join(name), [0, 1, 2]).unwrap();
+        }
+        let file = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let mut api = RouterBuilder::new();
+        api.mime_type("dat", "application/x-api", Disposition::Inline)
+            .resource("/files", "b.dat", file("b.dat"))
+            .resource("/files", "c.log", file("c.log"));
+        let mut builder = RouterBuilder::new();
+        builder
+            .mime_type("log", "text/x-log", Disposition::Inline)
+            .resource("/files", "a.dat", file("a.dat"))
+            .nest("/api", api);
+        let router = builder.build().unwrap();
+        let content_type = |path: &str| {
+            let response = get(&router, path).unwrap();
+            response.headers()["Content-Type"]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(content_type("/files/a.dat"), "application/octet-stream");
+        assert_eq!(content_type("/api/files/b.dat"), "application/x-api");
+        assert_eq!(content_type("/api/files/c.log"), "text/x-log");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn cors_router() -> Router {
+        let mut builder = RouterBuilder::new();
+        builder
+            .cors(Cors::new().origin("https://dashboard.local"))
+            .get("/lights", args)
+            .put("/lights", args);
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn rejects_cors_on_nested_routers() {
+        let mut builder = RouterBuilder::new();
+        builder.group("/api", |api| {
+            api.cors(Cors::new().origin("https://dashboard.local"))
+                .get("/lights", args);
+        });
+        let errors = builder.build().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].error,
+            "Misplaced cors: /api allows cross-origin requests, only the root router can"
+        );
+    }
+
+    #[test]
+    fn answers_preflights_without_credentials() {
+        let router = cors_router();
+        let headers = [
+            ("Origin", "https://dashboard.local"),
+            ("Access-Control-Request-Method", "PUT"),
+        ];
+        let response = router
+            .resolve(request(Method::OPTIONS, "/lights", &headers), &auth())
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let methods = response.headers().get("Access-Control-Allow-Methods");
+        assert_eq!(methods.unwrap(), "GET, PUT");
+
+        let error = router
+            .resolve(request(Method::OPTIONS, "/nope", &headers), &auth())
+            .unwrap_err();
+        assert_eq!(error.code(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn answers_options_with_allowed_methods() {
+        let router = cors_router();
+        let uri = "/lights?username=user&password=pass";
+        let response = router
+            .resolve(request(Method::OPTIONS, uri, &[]), &auth())
+            .unwrap();
+        assert_eq!(response.headers().get(ALLOW).unwrap(), "GET, PUT, OPTIONS");
+        let vary = response.headers().get("Vary");
+        assert_eq!(vary.unwrap(), "Origin");
+    }
+
+    #[test]
+    fn rejects_credentials_for_any_origin() {
+        let mut builder = RouterBuilder::new();
+        builder
+            .cors(Cors::new().any_origin().credentials(true))
+            .get("/lights", args);
+        assert_eq!(builder.build().unwrap_err().len(), 1);
+    }
+
+    fn policy_router(trailing_slash: PathPolicy, case: PathPolicy) -> Router {
+        let mut builder = RouterBuilder::new();
+        builder
+            .trailing_slash(trailing_slash)
+            .case(case)
+            .get("/api/status", args)
+            .get("/docs/", args)
+            .get("/devices/[id]/edit", args)
+            .resource("/files", "Report.pdf", "/tmp/report.pdf");
+        builder.build().unwrap()
+    }
+
+    fn status(router: &Router, path: &str) -> StatusCode {
+        match get(router, path) {
+            Ok(response) => response.status(),
+            Err(e) => e.code(),
+        }
+    }
+
+    fn location(router: &Router, path: &str) -> String {
+        let response = get(router, path).unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        let location = response.headers().get(LOCATION).unwrap();
+        location.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn rejects_path_policies_on_nested_routers() {
+        let mut builder = RouterBuilder::new();
+        builder
+            .trailing_slash(PathPolicy::Redirect)
+            .group("/api", |api| {
+                api.trailing_slash(PathPolicy::Strict)
+                    .case(PathPolicy::Lenient)
+                    .get("/status", args);
+            });
+        let errors = builder.build().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].error,
+            "Misplaced trailing_slash: /api sets a path policy, only the root router can"
+        );
+        assert_eq!(
+            errors[1].error,
+            "Misplaced case: /api sets a path policy, only the root router can"
+        );
+    }
+
+    #[test]
+    fn trailing_slashes_are_ignored_by_default() {
+        let router = RouterBuilder::new()
+            .get("/api/status", args)
+            .build()
+            .unwrap();
+        assert_eq!(status(&router, "/api/status"), StatusCode::OK);
+        assert_eq!(status(&router, "/api/status/"), StatusCode::OK);
+        assert_eq!(status(&router, "/API/status"), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn strict_policies_match_exactly() {
+        let router = policy_router(PathPolicy::Strict, PathPolicy::Strict);
+        assert_eq!(status(&router, "/api/status"), StatusCode::OK);
+        assert_eq!(status(&router, "/api/status/"), StatusCode::NOT_FOUND);
+        assert_eq!(status(&router, "/docs/"), StatusCode::OK);
+        assert_eq!(status(&router, "/docs"), StatusCode::NOT_FOUND);
+        assert_eq!(status(&router, "/Api/Status"), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn lenient_case_matches_any_case() {
+        let router = policy_router(PathPolicy::Strict, PathPolicy::Lenient);
+        assert_eq!(status(&router, "/API/Status"), StatusCode::OK);
+        // Variables keep the case they were sent with
+        assert_eq!(
+            body(get(&router, "/Devices/AbC/EDIT").unwrap()),
+            r#"[("id", "AbC")]"#
+        );
+    }
+
+    #[test]
+    fn redirects_to_the_registered_path() {
+        let router = policy_router(PathPolicy::Redirect, PathPolicy::Redirect);
+        let query = "?username=user&password=pass";
+        assert_eq!(
+            location(&router, "/api/status/"),
+            format!("/api/status{}", query)
+        );
+        assert_eq!(location(&router, "/docs"), format!("/docs/{}", query));
+        assert_eq!(
+            location(&router, "/API/Status/"),
+            format!("/api/status{}", query)
+        );
+        assert_eq!(
+            location(&router, "/devices/a%20b/Edit"),
+            format!("/devices/a%20b/edit{}", query)
+        );
+        assert_eq!(
+            location(&router, "/FILES/report.PDF"),
+            format!("/files/Report.pdf{}", query)
+        );
+        assert_eq!(status(&router, "/api/status"), StatusCode::OK);
+        assert_eq!(status(&router, "/api/other"), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn redirects_need_the_route_credentials() {
+        let router = policy_router(PathPolicy::Redirect, PathPolicy::Strict);
+        let request = request(Method::GET, "/api/status/", &[]);
+        let error = router.resolve(request, &auth()).unwrap_err();
+        assert_eq!(error.code(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn folded_case_conflicts() {
+        let mut builder = RouterBuilder::new();
+        builder
+            .case(PathPolicy::Lenient)
+            .get("/api/status", args)
+            .get("/API/status", args);
+        assert_eq!(builder.build().unwrap_err().len(), 1);
+
+        let mut builder = RouterBuilder::new();
+        builder.get("/api/status", args).get("/API/status", args);
+        assert!(builder.build().is_ok());
     }
 }
@@ -0,0 +1,305 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use http::StatusCode;
+use percent_encoding::utf8_percent_encode;
+
+use crate::server::{
+    mime::MimeTypes,
+    request::ServerRequest,
+    response::{IntoResponse, ServerResponse},
+    router::parser::SEGMENT,
+    ServerError, ServerResult,
+};
+
+use super::router::{redirect, RouteText};
+
+// A static directory is mounted on a route and serves every file below its root, the segments of
+// the query path after the mount point are resolved relative to the root
+#[derive(Debug, Clone)]
+pub struct StaticDir {
+    pub root: PathBuf,
+    pub index: Option<RouteText>,
+    pub listing: bool,
+}
+
+impl StaticDir {
+    pub fn new(root: &str) -> Self {
+        Self {
+            root: PathBuf::from(root),
+            index: Some(RouteText::Borrowed("index.html")),
+            listing: false,
+        }
+    }
+
+    pub fn index(mut self, index: impl Into<RouteText>) -> Self {
+        self.index = Some(index.into());
+        self
+    }
+
+    pub fn no_index(mut self) -> Self {
+        self.index = None;
+        self
+    }
+
+    pub fn listing(mut self, listing: bool) -> Self {
+        self.listing = listing;
+        self
+    }
+
+    // Directories are only served with a trailing slash, so the links of their index and listing
+    // resolve inside of them. Requests without one are redirected to it
+    pub fn serve(
+        &self,
+        segments: &[String],
+        request: &ServerRequest,
+        mime: &MimeTypes,
+    ) -> ServerResult<ServerResponse> {
+        let path = self.locate(segments)?;
+        if !path.is_dir() {
+            return ServerResponse::file_with(&path_str(&path)?, mime, None);
+        }
+        let request_path = request.path();
+        if !request_path.ends_with('/') {
+            return redirect(&format!("{}/", request_path), request.query().raw());
+        }
+        if let Some(index) = &self.index {
+            let index = path.join(index.as_ref());
+            if index.is_file() {
+                return ServerResponse::file_with(&path_str(&index)?, mime, None);
+            }
+        }
+        if self.listing {
+            return Ok(ServerResponse::html(list_directory(&path, request_path)?));
+        }
+        Err(not_found())
+    }
+
+    // Maps the segments to a path inside the root, hidden entries and anything resolving outside of
+    // the root (.., symlinks) are treated as missing
+    fn locate(&self, segments: &[String]) -> ServerResult<PathBuf> {
+        let root = fs::canonicalize(&self.root).map_err(|e| {
+            ServerError::err(&format!("Error opening static directory: {}", e)).log()
+        })?;
+        let mut path = root.clone();
+        for segment in segments {
+            if segment.starts_with('.') || segment.contains(['/', '\\']) {
+                return Err(not_found());
+            }
+            path.push(segment);
+        }
+        let path = fs::canonicalize(&path).map_err(|_| not_found())?;
+        if !path.starts_with(&root) {
+            return Err(not_found());
+        }
+        Ok(path)
+    }
+}
+
+fn list_directory(path: &Path, request_path: &str) -> ServerResult<String> {
+    let entries = fs::read_dir(path).map_err(|_| not_found())?;
+    let mut names = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            if name.starts_with('.') {
+                return None;
+            }
+            match e.file_type() {
+                Ok(t) if t.is_dir() => Some(format!("{}/", name)),
+                Ok(_) => Some(name),
+                Err(_) => None,
+            }
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+
+    let base = request_path.trim_end_matches('/');
+    let title = escape_html(if base.is_empty() { "/" } else { base });
+    let items = names
+        .iter()
+        .map(|name| {
+            let href = match name.strip_suffix('/') {
+                Some(dir) => format!("{}/", utf8_percent_encode(dir, SEGMENT)),
+                None => utf8_percent_encode(name, SEGMENT).to_string(),
+            };
+            format!(
+                "<li><a href=\"{}/{}\">{}</a></li>\n",
+                escape_html(base),
+                escape_html(&href),
+                escape_html(name)
+            )
+        })
+        .collect::<String>();
+    Ok(format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<ul>\n{items}</ul>\n</body>\n</html>\n"
+    ))
+}
+
+pub fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn path_str(path: &Path) -> ServerResult<String> {
+    path.to_str()
+        .map(str::to_string)
+        .ok_or(ServerError::new(StatusCode::NOT_FOUND, "Invalid file name"))
+}
+
+fn not_found() -> ServerError {
+    ServerError::new(StatusCode::NOT_FOUND, "File not found")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use http::{header::LOCATION, Request};
+
+    use super::*;
+    use crate::server::response::ResponseBody;
+
+    // A root with an index, a directory without one, hidden entries and symlinks leading out of it
+    fn fixture(name: &str) -> PathBuf {
+        let base = std::env::temp_dir().join(format!("static-dir-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let root = base.join("root");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("files/sub dir")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(base.join("outside")).unwrap();
+        fs::write(root.join("app.css"), "body {}").unwrap();
+        fs::write(root.join("docs/index.html"), "<a href=\"page.html\">").unwrap();
+        fs::write(root.join("files/a.txt"), "a").unwrap();
+        fs::write(root.join("files/.env"), "SECRET=1").unwrap();
+        fs::write(root.join(".git/config"), "").unwrap();
+        fs::write(base.join("outside/secret.txt"), "secret").unwrap();
+        symlink(base.join("outside"), root.join("escape")).unwrap();
+        symlink(base.join("outside/secret.txt"), root.join("secret.txt")).unwrap();
+        base
+    }
+
+    fn serve(dir: &StaticDir, path: &str) -> ServerResult<ServerResponse> {
+        let request = ServerRequest::new(Request::get(path).body(None).unwrap());
+        let segments = request
+            .path()
+            .split('/')
+            .skip(2)
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| {
+                percent_encoding::percent_decode_str(segment)
+                    .decode_utf8_lossy()
+                    .into_owned()
+            })
+            .collect::<Vec<_>>();
+        dir.serve(&segments, &request, &MimeTypes::default())
+    }
+
+    fn served_file(response: ServerResponse) -> PathBuf {
+        match response.into_body() {
+            Some(ResponseBody::File(file)) => file.path,
+            _ => panic!("Expected a file body"),
+        }
+    }
+
+    fn body(response: ServerResponse) -> String {
+        match response.into_body() {
+            Some(ResponseBody::Bytes(bytes)) => String::from_utf8(bytes).unwrap(),
+            _ => panic!("Expected an in memory body"),
+        }
+    }
+
+    fn status(dir: &StaticDir, path: &str) -> StatusCode {
+        match serve(dir, path) {
+            Ok(response) => response.status(),
+            Err(e) => e.code(),
+        }
+    }
+
+    #[test]
+    fn serves_files_and_indexes() {
+        let base = fixture("index");
+        let dir = StaticDir::new(base.join("root").to_str().unwrap());
+        let root = fs::canonicalize(base.join("root")).unwrap();
+        let file = served_file(serve(&dir, "/static/app.css").unwrap());
+        assert_eq!(file, root.join("app.css"));
+        let index = served_file(serve(&dir, "/static/docs/").unwrap());
+        assert_eq!(index, root.join("docs/index.html"));
+        assert_eq!(status(&dir, "/static/missing.css"), StatusCode::NOT_FOUND);
+        // Without an index or a listing, a directory is missing
+        assert_eq!(status(&dir, "/static/files/"), StatusCode::NOT_FOUND);
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn redirects_directories_to_a_trailing_slash() {
+        let base = fixture("redirect");
+        let dir = StaticDir::new(base.join("root").to_str().unwrap());
+        let response = serve(&dir, "/static/docs?lang=en").unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()[LOCATION], "/static/docs/?lang=en");
+        let response = serve(&dir, "/static").unwrap();
+        assert_eq!(response.headers()[LOCATION], "/static/");
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn lists_directories_without_hidden_entries() {
+        let base = fixture("listing");
+        let dir = StaticDir::new(base.join("root").to_str().unwrap()).listing(true);
+        let listing = body(serve(&dir, "/static/files/").unwrap());
+        assert!(listing.contains("<a href=\"/static/files/a.txt\">a.txt</a>"));
+        assert!(listing.contains("<a href=\"/static/files/sub%20dir/\">sub dir/</a>"));
+        assert!(!listing.contains(".env"));
+        let listing = body(serve(&dir, "/static/").unwrap());
+        assert!(!listing.contains(".git"));
+        // The index still wins over the listing
+        let index = served_file(serve(&dir, "/static/docs/").unwrap());
+        assert!(index.ends_with("docs/index.html"));
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn uses_the_configured_index() {
+        let base = fixture("custom-index");
+        let root = base.join("root");
+        let dir = StaticDir::new(root.to_str().unwrap()).index(format!("{}.txt", "a"));
+        let index = served_file(serve(&dir, "/static/files/").unwrap());
+        assert_eq!(index, root.join("files/a.txt"));
+        assert_eq!(status(&dir, "/static/docs/"), StatusCode::NOT_FOUND);
+        let dir = StaticDir::new(root.to_str().unwrap())
+            .no_index()
+            .listing(true);
+        let listing = body(serve(&dir, "/static/docs/").unwrap());
+        assert!(listing.contains("index.html</a>"));
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn stays_inside_the_root() {
+        let base = fixture("traversal");
+        let dir = StaticDir::new(base.join("root").to_str().unwrap()).listing(true);
+        for path in [
+            "/static/../outside/secret.txt",
+            "/static/files/../../outside/secret.txt",
+            "/static/..%2Foutside%2Fsecret.txt",
+            "/static/files%2F..%2F..%2Foutside%2Fsecret.txt",
+            "/static/..%5Coutside%5Csecret.txt",
+            "/static/escape/secret.txt",
+            "/static/escape/",
+            "/static/secret.txt",
+            "/static/files/.env",
+            "/static/.git/config",
+            "/static/.git/",
+        ] {
+            assert_eq!(status(&dir, path), StatusCode::NOT_FOUND, "{}", path);
+        }
+        fs::remove_dir_all(base).unwrap();
+    }
+}
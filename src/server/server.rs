@@ -5,7 +5,7 @@ use std::{
     sync::Arc,
 };
 
-use http::StatusCode;
+use http::{Method, StatusCode};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use tokio::{
     net::TcpListener,
@@ -15,11 +15,16 @@ use tokio::{
 use tokio_rustls::TlsAcceptor;
 
 use super::{
-    auth::{AuthManager, Authentication},
+    auth::AuthManager,
+    compression::{self, Compression},
+    conditional,
     connection::Connection,
-    request::ServerRequest,
+    error_page::{self, ErrorRenderer},
+    form::FormLimits,
+    range,
+    request::{BodyLimits, ServerRequest},
     response::ServerResponse,
-    router::router::Router,
+    router::{handle::RouterHandle, router::Router},
     ServerError, ServerResult,
 };
 
@@ -28,6 +33,10 @@ pub struct ServerConfig {
     pub server_address: SocketAddr,
     pub max_workers: usize,
     pub ss_dir: &'static str,
+    pub compression: Compression,
+    pub body_limits: BodyLimits,
+    pub form_limits: FormLimits,
+    pub error_renderer: ErrorRenderer,
 }
 
 impl Default for ServerConfig {
@@ -36,14 +45,18 @@ impl Default for ServerConfig {
             server_address: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8080),
             max_workers: 5,
             ss_dir: "/tmp/ssl/",
+            compression: Compression::default(),
+            body_limits: BodyLimits::default(),
+            form_limits: FormLimits::default(),
+            error_renderer: error_page::text,
         }
     }
 }
 
 pub struct Server {
-    config: ServerConfig,
+    config: Arc<ServerConfig>,
     tls: TlsAcceptor,
-    routes: Arc<Router>,
+    routes: RouterHandle,
     auth: Arc<AuthManager>,
     worker_pool: Arc<Semaphore>,
 }
@@ -62,13 +75,18 @@ impl Server {
 
         Ok(Self {
             worker_pool: Arc::new(Semaphore::new(config.max_workers)),
-            config,
+            config: Arc::new(config),
             auth: Arc::new(auth),
-            routes: Arc::new(routes),
+            routes: RouterHandle::new(routes),
             tls,
         })
     }
 
+    // Handle to swap the routes while the server runs, it stays valid after run
+    pub fn routes(&self) -> RouterHandle {
+        self.routes.clone()
+    }
+
     pub fn run(self) -> JoinHandle<ServerResult<()>> {
         tokio::spawn(async move {
             let listener = TcpListener::bind(self.config.server_address)
@@ -85,7 +103,13 @@ impl Server {
                     .map_err(|e| {
                         ServerError::err(&format!("Error getting permit for worker: {e}"))
                     })?;
-                ServerWorker::spawn(self.auth.clone(), self.routes.clone(), con, permit);
+                ServerWorker::spawn(
+                    self.config.clone(),
+                    self.auth.clone(),
+                    self.routes.clone(),
+                    con,
+                    permit,
+                );
             }
         })
     }
@@ -109,19 +133,22 @@ impl Server {
 }
 
 struct ServerWorker {
+    config: Arc<ServerConfig>,
     auth: Arc<AuthManager>,
-    routes: Arc<Router>,
+    routes: RouterHandle,
     connection: Connection,
 }
 
 impl ServerWorker {
     pub fn spawn(
+        config: Arc<ServerConfig>,
         auth: Arc<AuthManager>,
-        routes: Arc<Router>,
+        routes: RouterHandle,
         connection: Connection,
         permit: OwnedSemaphorePermit,
     ) -> JoinHandle<ServerResult<()>> {
         let mut worker = Self {
+            config,
             auth,
             routes,
             connection,
@@ -133,7 +160,15 @@ impl ServerWorker {
         println!("Connection from: {}", self.connection.from);
         let result = match self.handle().await {
             Ok(response) => self.connection.reply(response).await,
-            Err(e) => self.connection.reply_error(e).await,
+            Err(e) => {
+                // Internal errors only reach the client as their status, the details are logged
+                let e = match e.code().is_server_error() {
+                    true => e.log(),
+                    false => e,
+                };
+                let render = self.config.error_renderer;
+                self.connection.reply_error(e, render).await
+            }
         };
         permit.semaphore().add_permits(1);
         permit.forget();
@@ -148,17 +183,29 @@ impl ServerWorker {
             ));
         };
 
-        let request = ServerRequest::from_connection(&mut self.connection).await?;
-        let auth = Authentication::from_request(&request)?;
-
-        if !self.auth.authenticate(&auth) {
-            return Err(ServerError::new(
-                StatusCode::UNAUTHORIZED,
-                "Authentication failed",
-            ));
+        // The routes are loaded once so the request is authorized and resolved by the same router
+        let routes = self.routes.load();
+        let request = ServerRequest::from_connection(
+            &mut self.connection,
+            &self.config.body_limits,
+            &self.config.form_limits,
+            |request| routes.authorize(request, &self.auth),
+        )
+        .await?;
+        let head = request.head();
+        let response = routes.resolve(request, &self.auth)?;
+        let response = match head.method {
+            Method::GET => conditional::with_etag(response),
+            _ => response,
         };
-
-        self.routes.resolve(request)
+        let response = compression::vary(&self.config.compression, response);
+        let response = conditional::apply(&head, response);
+        let response = range::apply(&head, response)?;
+        Ok(compression::apply(
+            &self.config.compression,
+            &head,
+            response,
+        ))
     }
 }
 